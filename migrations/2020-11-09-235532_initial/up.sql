@@ -7,13 +7,14 @@ CREATE TABLE IF NOT EXISTS Directories (
 CREATE TABLE IF NOT EXISTS Files (
     id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
     directory_id INTEGER NOT NULL,
-    filename TEXT NOT NULL UNIQUE,
+    filename TEXT NOT NULL,
     hash BLOB NOT NULL,
+    UNIQUE(directory_id, filename),
     FOREIGN KEY (directory_id) REFERENCES Directories(id) ON DELETE CASCADE ON UPDATE CASCADE
 );
 
 CREATE INDEX idx_dir_path ON Directories(path);
-CREATE INDEX idx_file_name ON Directories(filename);
+CREATE INDEX idx_file_name ON Files(filename);
 CREATE INDEX idx_files_hash ON Files(hash);
 
 CREATE TABLE IF NOT EXISTS DirectoryMetadata (
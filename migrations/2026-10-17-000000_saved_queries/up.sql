@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS SavedQueries (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    query TEXT NOT NULL
+);
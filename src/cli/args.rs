@@ -7,15 +7,20 @@ use crate::format::str::StrExtensions;
 use colored::{Colorize};
 use crate::cli::query::args::{Args, ArgsIter};
 use std::process::exit;
-use crate::cli::query::parse::{OrQuery, ParseError, parse};
+use crate::cli::query::parse::{Query, ParseError, parse};
 use std::collections::HashMap;
 use crate::cli::args::SubcommandParseError::{MissingFlagValue, UnknownFlag, ExtraPositionalArgument, UnexpectedPositionalArgument, NotEnoughPositionalArguments};
 use crate::cli::query::lex::{lex, LexError};
-use super::subcommands::{get, list, remove, set};
+use crate::cli::query::diagnostic::{report_lex_error, report_parse_error, report_unknown_keys};
+use super::subcommands::{get, list, query, remove, set, stats};
 use crate::cli::help::{print_help, print_version};
 use crate::cli::print::{log, Logger};
 use crate::cli::typo::typos_threshold;
 use crate::cli::lang;
+use crate::cli::db::open_database;
+use crate::database::database::Database;
+use crate::database::sqlite::SqliteDatabase;
+use crate::cli::query::explain::print_explanation;
 
 bitflags! {
     pub struct FileSelector: u8 {
@@ -27,13 +32,17 @@ bitflags! {
 
 pub enum FileEntryExpr {
     List(Vec<String>),
-    Expr(OrQuery)
+    Expr(Query)
 }
 
 pub struct SubcommandParseResults {
     flags: Vec<(Flag, Option<String>, String)>,
     positional: Vec<String>,
+    // where each positional argument starts in the command line
+    positional_index: Vec<usize>,
     expr: Option<FileEntryExpr>,
+    inherited: bool,
+    cmdline: String,
 }
 
 pub struct ArgError {
@@ -42,6 +51,41 @@ pub struct ArgError {
     pub cmdline: String
 }
 
+impl SubcommandParseResults {
+    pub fn has_flag(&self, flag: &Flag) -> bool {
+        self.flags.iter().any(|f| f.0 == *flag)
+    }
+
+    pub fn flag_value(&self, flag: &Flag) -> Option<&str> {
+        self.flags.iter().find(|f| f.0 == *flag).and_then(|f| f.1.as_deref())
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    /// Returns the positional arguments from the nth on, paired with where each starts in the command line like lex() takes them.
+    pub fn positional_args(&self, n: usize) -> Vec<(String, usize)> {
+        self.positional.iter().cloned()
+            .zip(self.positional_index.iter().copied())
+            .skip(n)
+            .collect()
+    }
+
+    pub fn cmdline(&self) -> &str {
+        &self.cmdline
+    }
+
+    pub fn expr(&self) -> Option<&FileEntryExpr> {
+        self.expr.as_ref()
+    }
+
+    /// Returns true if entries should also have the metadata of the directories above them, the same as the 'where' query does.
+    pub fn inherited(&self) -> bool {
+        self.inherited
+    }
+}
+
 impl ArgError {
     pub fn new(arg: String, position: usize, cmdline: &str) -> Self {
         ArgError { arg, position, cmdline: cmdline.to_owned() }
@@ -61,6 +105,7 @@ pub enum SubcommandParseError<'a> {
 pub fn parse_subcommand<'a, I: Iterator<Item=(String, usize)>>(sc: &'a Subcommand, mut args: I, cmdline: &str) -> Result<SubcommandParseResults, SubcommandParseError<'a>> {
     let mut flags = Vec::new();
     let mut positional = Vec::new();
+    let mut positional_index = Vec::new();
     let mut expr = None;
     let long_map = sc.flags.iter()
         .map(|x| (x.long.trim_start_matches('-'), x))
@@ -72,7 +117,7 @@ pub fn parse_subcommand<'a, I: Iterator<Item=(String, usize)>>(sc: &'a Subcomman
 
     let mut parse_flags = true;
 
-    'outer: for (arg, index) in args {
+    'outer: while let Some((arg, index)) = args.next() {
         if parse_flags {
             if arg == "--" {
                 parse_flags = false;
@@ -123,14 +168,15 @@ pub fn parse_subcommand<'a, I: Iterator<Item=(String, usize)>>(sc: &'a Subcomman
             }
         }
 
-        if arg == "where" {
-            let mut lexemes = lex(args, cmdline).map_err(SubcommandParseError::LexError)?;
-            let query = parse(&mut lexemes).map_err(SubcommandParseError::LexError)?;
+        if arg == "where" && sc.file_selector.contains(FileSelector::QUERY) {
+            let rest = args.into_vec();
+            let mut lexemes = lex(&rest, cmdline).map_err(SubcommandParseError::LexError)?;
+            let query = parse(&mut lexemes).map_err(SubcommandParseError::ParseError)?;
             expr = Some(FileEntryExpr::Expr(query));
             break;
         }
 
-        if arg == "from" {
+        if arg == "from" && sc.file_selector.contains(FileSelector::FILE_LIST) {
             let vec = args.map(|x| x.0).into_vec();
             expr = Some(FileEntryExpr::List(vec));
             break;
@@ -139,6 +185,7 @@ pub fn parse_subcommand<'a, I: Iterator<Item=(String, usize)>>(sc: &'a Subcomman
         match &sc.positional {
             Some(p) => {
                 positional.push(arg);
+                positional_index.push(index);
                 if let Some(max) = p.count.1 {
                     if positional.len() > max {
                         return Err(ExtraPositionalArgument(p, ArgError::new(arg, index, cmdline)))
@@ -150,12 +197,18 @@ pub fn parse_subcommand<'a, I: Iterator<Item=(String, usize)>>(sc: &'a Subcomman
     }
 
     if let Some(p) = &sc.positional {
-        if positional.len() < p.count.1.unwrap_or(0) {
+        if positional.len() < p.count.0.unwrap_or(0) {
             return Err(NotEnoughPositionalArguments(p))
         }
     }
 
-    Ok(SubcommandParseResults {flags, positional, expr})
+    // read once here, so the query and the subcommand can't disagree about it
+    let inherited = flags.iter().any(|f| f.0 == INHERITED_FLAG);
+    if let Some(FileEntryExpr::Expr(q)) = &mut expr {
+        q.inherited = inherited;
+    }
+
+    Ok(SubcommandParseResults {flags, positional, positional_index, expr, inherited, cmdline: cmdline.to_owned()})
 }
 
 #[derive(Eq, PartialEq, Clone, Debug)]
@@ -179,7 +232,7 @@ pub struct Subcommand {
     pub(crate) positional: Option<Positional>,
     pub(crate) file_selector: FileSelector,
     pub(crate) flags: Vec<Flag>,
-    pub(crate) on_parse: Box<dyn FnOnce(SubcommandParseResults)>,
+    pub(crate) on_parse: fn(SubcommandParseResults),
 }
 
 pub static ASSIGN_RE: &Regex = regex_expect(r"^([a-zA-Z0-9_-]+)=(.+)$");
@@ -201,7 +254,7 @@ pub static KEY_RE: &Regex = regex_expect("^([a-zA-Z0-9_-])+$");
 /// assert_eq!(key_list(&["abc", ",def"]), Ok(vec!["abc", "def"]));
 /// assert_eq!(key_list(&["abc, def"]), Ok(vec!["abc", "def"]));
 /// ```
-fn key_list<'a>(args: &[&'a str]) -> Result<Vec<&'a str>, usize> {
+pub fn key_list<'a>(args: &[&'a str]) -> Result<Vec<&'a str>, usize> {
     let mut ret = Vec::<&str>::new();
     let mut comma: bool = false;
 
@@ -250,23 +303,82 @@ pub static RECURSIVE_FLAG: Flag = Flag {
     description: "The command will be recursively applied to the contents of any directories given."
 };
 
+pub static EXPLAIN_FLAG: Flag = Flag {
+    aliases: vec!["--explain"],
+    equals_name: None,
+    description: "Instead of running the command, shows how its 'where' query was parsed, the SQL it compiles to, and the database's plan for running it."
+};
+
+pub static INHERITED_FLAG: Flag = Flag {
+    aliases: vec!["--inherited"],
+    equals_name: None,
+    description: "Files and directories also have the metadata of the directories above them, unless they or a nearer directory have the same key."
+};
+
+pub static STRICT_KEYS_FLAG: Flag = Flag {
+    aliases: vec!["--strict-keys"],
+    equals_name: None,
+    description: "Fails instead of warning when the 'where' query mentions a key that no file or directory has."
+};
+
 static SUBCOMMANDS: &[Subcommand] = &[
     get::SUBCOMMAND,
     list::SUBCOMMAND,
     set::SUBCOMMAND,
-    remove::SUBCOMMAND
+    remove::SUBCOMMAND,
+    query::SUBCOMMAND,
+    stats::SUBCOMMAND
 ];
 
 static FLAGS: &[Flag] = &[
     HELP_FLAG
 ];
 
+/// Logs where in the command line an argument is.
+fn log_arg(a: &ArgError) {
+    log().cmdline(&a.cmdline, a.position, a.arg.chars().count());
+}
+
+/// Warns about any key in the 'where' query that no entry has, exiting if the command was given --strict-keys.
+pub fn check_query_keys(db: &SqliteDatabase, res: &SubcommandParseResults) {
+    let query = match res.expr() {
+        Some(FileEntryExpr::Expr(q)) if !q.keys().is_empty() => q,
+        _ => return
+    };
+
+    let known = match db.metadata_keys::<Vec<String>>() {
+        Ok(k) => k,
+        Err(e) => {
+            log().error(&format!("Failed to read the keys in the database: {}", e));
+            exit(1);
+        }
+    };
+
+    let strict = res.has_flag(&STRICT_KEYS_FLAG);
+
+    if report_unknown_keys(query, &known, strict) && strict {
+        exit(1);
+    }
+}
+
+/// Returns a " Did you mean ...?" sentence if `arg` looks like a misspelled 'from' or 'where'.
+fn did_you_mean_keyword(arg: &str) -> String {
+    let typos = typos_threshold(arg, KEYWORDS.iter().copied(), 0.5, 2);
+
+    if typos.is_empty() {
+        return "".to_owned();
+    }
+
+    let words = typos.iter().map(|x| x.0.bold().yellow().to_string()).into_vec();
+    format!(" Did you mean {}?", lang::or(words.iter().map(|x| x.as_str())))
+}
+
 pub fn parse_command_line_args() -> () {
     let raw = env::args().into_vec();
-    let args = Args::new(raw.iter().collect());
-    let a = args.iter().skip(1);
+    let args = Args::new(&raw.iter().map(|x| x.as_str()).into_vec());
+    let mut a = args.iter().skip(1);
 
-    for (arg, index) in a {
+    while let Some((arg, _)) = a.next() {
         match arg.to_lowercase().as_str() {
             "--help" | "-h" | "help" => {
                 print_help(SUBCOMMANDS, FLAGS, &args[0].0);
@@ -281,64 +393,106 @@ pub fn parse_command_line_args() -> () {
 
         for sc in SUBCOMMANDS {
             if sc.name == arg.to_lowercase() {
-                let log_cmdline = || log().cmdline(&a.cmdline, a.position, a.arg.chars().count());
-
-                let res = match parse_subcommand(sc, a, args.cmdline()) {
+                let res = match parse_subcommand(sc, &mut a, args.cmdline()) {
                     Ok(s) => s,
-                    Err(e) => match e {
-                        MissingFlagValue(e, a) => {
-                            log().error(&format!("The flag {0} is missing a value. Specify {0}={1} or {0} {1}", a.arg.bold().yellow(), "value".green().italic()));
-                            log_cmdline();
-                            exit(0);
-                        }
-                        UnknownFlag(a) => {
-                            if sc.flags.len() == 0 {
+                    Err(e) => {
+                        match e {
+                            MissingFlagValue(e, a) => {
+                                log().error(&format!("The flag {0} is missing a value. Specify {0}={1} or {0} {1}", a.arg.bold().yellow(), "value".green().italic()));
+                                log_arg(&a);
+                            }
+                            UnknownFlag(a) => {
+                                if sc.flags.len() == 0 {
+                                    log().error(&format!(
+                                        "A flag {0} was given, but the {1} subcommand does not accept any flags. Type {2} {1} --help for more information. If you want to specify {0} as a positional argument, put it after a {3} argument.",
+                                        a.arg.bold().red(),
+                                        sc.name.bold().yellow(),
+                                        args[0].0,
+                                        "--".bold().green()
+                                    ));
+                                    log_arg(&a);
+                                    exit(1);
+                                }
+
+                                let typos = typos_threshold(&a.arg, sc.flags.iter().flat_map(|x| x.aliases.iter().copied()), 0.25, 2);
+                                let or = lang::or(typos.iter().map(|x| x.0)).split(", ").map(|x| x.yellow().bold().to_string()).into_vec().join(", ");
+
+                                if typos.len() == 0 {
+                                    log().error(&format!{
+                                        "An unknown flag {0} was given. Type {2} {1} --help for a list of accepted flags. If you want to specify {0} as a positional argument, put it after a {3} argument.",
+                                        a.arg.bold().red(),
+                                        sc.name.bold().yellow(),
+                                        args[0].0,
+                                        "--".bold().green()
+                                    });
+                                }
+                                else {
+                                    log().error(&format!{
+                                        "An unknown flag {0} was given. Did you mean {1}? Type {3} {2} --help for a list of accepted flags. If you want to specify {0} as a positional argument, put it after a {4} argument.",
+                                        a.arg.bold().red(),
+                                        or,
+                                        sc.name.bold().yellow(),
+                                        args[0].0,
+                                        "--".bold().green()
+                                    });
+                                }
+                                log_arg(&a);
+                            }
+                            SubcommandParseError::LexError(e) => report_lex_error(&e, args.cmdline()),
+                            SubcommandParseError::ParseError(e) => report_parse_error(&e, args.cmdline()),
+                            UnexpectedPositionalArgument(a) => {
                                 log().error(&format!(
-                                    "A flag {0} was given, but the {1} subcommand does not accept any flags. Type {2} {1} --help for more information. If you want to specify {0} as a positional argument, put it after a {3} argument.",
-                                    a.arg.bold().red(),
+                                    "The {0} subcommand does not take any arguments, but {1} was given.{2} Type {3} {0} --help for more information.",
                                     sc.name.bold().yellow(),
-                                    args[0].0,
-                                    "--".bold().green()
+                                    a.arg.bold().red(),
+                                    did_you_mean_keyword(&a.arg),
+                                    args[0].0
                                 ));
-                                log_cmdline();
-                                return;
+                                log_arg(&a);
                             }
-
-                            let typos = typos_threshold(&a.arg, sc.flags.iter().flat_map(|x| x.aliases), 0.25, 2);
-                            let or = lang::or(typos.iter().map(|x| x.0)).split(", ").map(|x| x.yellow().bold()).into_vec().join(", ");
-
-                            if typos.len() == 0 {
-                                log().error(&format!{
-                                    "An unknown flag {0} was given. Type {2} {1} --help for a list of accepted flags. If you want to specify {0} as a positional argument, put it after a {3} argument.",
-                                    a.arg.bold().red(),
+                            ExtraPositionalArgument(p, a) => {
+                                log().error(&format!(
+                                    "The {0} subcommand takes at most {1} {2} argument(s), so {3} is one too many.{4} Type {5} {0} --help for more information.",
                                     sc.name.bold().yellow(),
-                                    args[0].0,
-                                    "--".bold().green()
-                                });
-                            }
-                            else {
-                                log().error(&format!{
-                                    "An unknown flag {0} was given. Did you mean {1}? Type {3} {2} --help for a list of accepted flags. If you want to specify {0} as a positional argument, put it after a {4} argument.",
+                                    p.count.1.unwrap_or(0),
+                                    p.name.italic(),
                                     a.arg.bold().red(),
-                                    or,
+                                    did_you_mean_keyword(&a.arg),
+                                    args[0].0
+                                ));
+                                log_arg(&a);
+                            }
+                            NotEnoughPositionalArguments(p) => {
+                                log().error(&format!(
+                                    "The {0} subcommand needs at least {1} {2} argument(s). {3}",
                                     sc.name.bold().yellow(),
-                                    args[0].0,
-                                    "--".bold().green()
-                                });
+                                    p.count.0.unwrap_or(0),
+                                    p.name.italic(),
+                                    p.description
+                                ));
+                                log().cmdline(args.cmdline(), args.cmdline().trim_end().chars().count(), 1);
                             }
-                            log_cmdline();
                         }
-                        SubcommandParseError::LexError(a) => {
-                            log().error(&format!("The token {0} was unrecognized.", a.arg.bold().red()));
-                            log_cmdline();
+                        exit(1);
+                    }
+                };
+
+                if res.has_flag(&EXPLAIN_FLAG) {
+                    match res.expr() {
+                        Some(FileEntryExpr::Expr(q)) => {
+                            let db = open_database();
+                            check_query_keys(&db, &res);
+                            print_explanation(&db, q)
                         }
-                        SubcommandParseError::ParseError(a) => {
+                        _ => {
+                            log().error(&format!("{} needs a query to explain. Give one after {}.", "--explain".bold().yellow(), "where".bold().green()));
+                            exit(1);
                         }
-                        UnexpectedPositionalArgument(_, _) => {}
-                        ExtraPositionalArgument(_, _, _) => {}
-                        NotEnoughPositionalArguments(_) => {}
                     }
+                    return;
                 }
+
+                (sc.on_parse)(res);
                 return;
             }
         }
@@ -0,0 +1,153 @@
+use std::collections::{HashMap, HashSet};
+use std::env::current_dir;
+use std::path::{Component, Path, PathBuf};
+use std::process::exit;
+use colored::Colorize;
+use crate::cli::print::{log, Logger};
+use crate::database::database::{Database, Entry};
+use crate::database::sqlite::{SqliteDatabase, SqliteError};
+use crate::filesystem::fs::{reposition_to_db, DB_NAME};
+
+/// Opens the database in the current directory or the nearest directory above it, moving into the directory containing it.
+///
+/// If there is no such database or it can't be opened, the problem is logged and the program exits.
+pub fn open_database() -> SqliteDatabase {
+    let path = match reposition_to_db() {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            log().error(&format!("No {} database was found in this directory or any directory above it.", DB_NAME.bold().yellow()));
+            exit(1);
+        }
+        Err(e) => {
+            log().error(&format!("Failed to search for the {} database: {}", DB_NAME.bold().yellow(), e));
+            exit(1);
+        }
+    };
+
+    match SqliteDatabase::new(&path) {
+        Ok(db) => db,
+        Err(e) => {
+            log().error(&format!("Failed to open the database at {}: {}", path.bold().yellow(), e));
+            exit(1);
+        }
+    }
+}
+
+/// Resolves paths given on the command line against the current directory.
+///
+/// This has to happen before `open_database`, which moves into the directory containing the database.
+pub fn absolute_paths(paths: &[String]) -> Vec<PathBuf> {
+    let cwd = match current_dir() {
+        Ok(d) => d,
+        Err(e) => {
+            log().error(&format!("Failed to get the current directory: {}", e));
+            exit(1);
+        }
+    };
+
+    paths.iter().map(|p| normalize(&cwd.join(p))).collect()
+}
+
+/// Turns the paths from `absolute_paths` into the paths the database stores them under, which are relative to the directory containing it.
+///
+/// This expects the current directory to be that directory, as it is after `open_database`. If a path is outside of it, the problem is logged and the program exits.
+pub fn db_paths(paths: &[PathBuf]) -> Vec<String> {
+    let root = match current_dir() {
+        Ok(d) => d,
+        Err(e) => {
+            log().error(&format!("Failed to get the directory containing the database: {}", e));
+            exit(1);
+        }
+    };
+
+    paths.iter().map(|p| match db_path(&root, p) {
+        Some(s) => s,
+        None => {
+            log().error(&format!("{} is not in {}, the directory containing the database.", p.to_string_lossy().bold().red(), root.to_string_lossy().bold().yellow()));
+            exit(1);
+        }
+    }).collect()
+}
+
+/// Returns the entries at the given paths in the order they were given, warning about the ones that aren't in the database.
+///
+/// If `recursive`, every directory is followed by the entries below it.
+pub fn path_entries(db: &SqliteDatabase, paths: &[String], recursive: bool, quiet: bool) -> Result<Vec<Entry>, SqliteError> {
+    let mut by_path = HashMap::new();
+    for e in db.get_entries::<Vec<Entry>, _>(paths.iter().map(|x| x.as_str()))? {
+        by_path.insert(db.entry_path(&e)?, e);
+    }
+
+    let mut ret = Vec::new();
+    let mut seen = HashSet::new();
+
+    for p in paths {
+        let entry = match by_path.get(p) {
+            Some(e) => e,
+            None => {
+                if !quiet {
+                    log().warn(&format!("{} is not in the database.", p.bold().yellow()));
+                }
+                continue;
+            }
+        };
+
+        let below = match entry {
+            Entry::Directory(d) if recursive => db.directory_entries::<Vec<Entry>>(d)?,
+            _ => Vec::new()
+        };
+
+        for e in std::iter::once(entry.clone()).chain(below) {
+            if seen.insert(entry_id(&e)) {
+                ret.push(e);
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Identifies an entry, since a file and a directory can have the same id.
+pub fn entry_id(e: &Entry) -> (bool, i32) {
+    match e {
+        Entry::File(f) => (true, f.id),
+        Entry::Directory(d) => (false, d.id)
+    }
+}
+
+/// Returns the path the database stores the entry at `path` under, or None if it isn't below `root` or isn't valid UTF-8.
+fn db_path(root: &Path, path: &Path) -> Option<String> {
+    let parts = path.strip_prefix(root).ok()?
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some("/".to_owned() + &parts.join("/"))
+}
+
+/// Removes the '.' and '..' components of a path without looking at the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut ret = PathBuf::new();
+
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                ret.pop();
+            }
+            _ => ret.push(c.as_os_str())
+        }
+    }
+
+    ret
+}
+
+#[test]
+fn test_db_path() {
+    let root = Path::new("/home/user/docs");
+
+    assert_eq!(db_path(root, &normalize(&root.join("a/./b/../c.txt"))), Some("/a/c.txt".to_owned()));
+    assert_eq!(db_path(root, &normalize(&root.join("."))), Some("/".to_owned()));
+    assert_eq!(db_path(root, &normalize(&root.join("a/../.."))), None);
+    assert_eq!(db_path(root, Path::new("/home/user/docs2/x")), None);
+}
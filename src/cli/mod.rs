@@ -6,3 +6,4 @@ pub mod print;
 pub mod help;
 pub mod typo;
 pub mod lang;
+pub mod db;
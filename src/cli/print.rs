@@ -1,16 +1,29 @@
-use std::cmp::max;
+use std::cmp::{max, min};
+use std::io::{stdout, ErrorKind, Write};
+use std::process::exit;
 use std::sync::{Mutex, MutexGuard};
-use colored::Colorize;
+use colored::{Color, Colorize};
 use crate::linq::collectors::IntoVec;
-use std::collections::VecDeque;
 
 struct PrintingContext {
     indent_level: usize,
     x_index: usize,
 }
 
+/// A column of a table printed by `PrintingContext::table`.
+pub struct TableColumn<'a> {
+    pub header: &'a str,
+    /// If the column has to be narrowed, its values lose their start instead of their end, which suits paths.
+    pub truncate_start: bool,
+}
+
+/// The space between the columns of a table.
+const COLUMN_GAP: usize = 2;
+/// How narrow a table column can get before the table is allowed to overflow the terminal instead.
+const MIN_COLUMN_WIDTH: usize = 4;
+
 static PRINTING_CTX: Mutex<PrintingContext> = Mutex::new(PrintingContext { indent_level: 0, x_index: 0 });
-static WIDTH: Option<usize> = term_size::dimensions_stdout().map(|x| x.1);
+static WIDTH: Option<usize> = term_size::dimensions_stdout().map(|x| x.0);
 
 pub fn width() -> Option<usize> {
     WIDTH
@@ -64,6 +77,36 @@ impl PrintingContext {
         self.newline();
     }
 
+    /// Prints a line as-is at the current indentation, without wrapping it or collapsing its whitespace.
+    pub fn preformatted(&mut self, s: &str) {
+        print!("{}", s);
+        self.x_index += s.chars().count();
+        self.newline();
+    }
+
+    /// Prints a path in bold followed by its key/value pairs, one per line with the '=' signs lined up, or '(no metadata)' if there are none.
+    ///
+    /// The keys are shown in `key_color`, and a pair's note, if it has one, is shown dimmed in parentheses after its value.
+    pub fn key_values(&mut self, path: &str, kv: &[(&str, &str, Option<String>)], key_color: Color) {
+        self.preformatted(&path.bold().to_string());
+
+        if kv.is_empty() {
+            self.preformatted(&format!("  {}", "(no metadata)".italic()));
+        }
+
+        let width = kv.iter().map(|x| x.0.chars().count()).max().unwrap_or(0);
+
+        for (k, v, note) in kv {
+            let note = match note {
+                Some(n) => format!(" {}", format!("({})", n).dimmed()),
+                None => "".to_owned()
+            };
+
+            // padded by hand, since the color codes would count towards a width given to format!
+            self.preformatted(&format!("  {}{} = {}{}", k.color(key_color), " ".repeat(width - k.chars().count()), v, note));
+        }
+    }
+
     pub fn space(&mut self, count: usize) {
         if !exceeds_width(self.indent_level + count) {
             print!("{}", " ".repeat(count));
@@ -86,6 +129,130 @@ impl PrintingContext {
             self.x_index += cl;
         }
     }
+
+    /// Prints rows as a table with aligned columns, preceded by the column headers if `header`.
+    ///
+    /// If the table is wider than the terminal, the widest columns are narrowed and their values cut short with a '…'. Control characters in the values are shown as spaces so they don't break up the rows.
+    pub fn table(&mut self, columns: &[TableColumn], rows: &[Vec<String>], header: bool) {
+        let rows = rows.iter()
+            .map(|r| r.iter().map(|c| c.chars().map(|x| if x.is_control() { ' ' } else { x }).collect::<String>()).into_vec())
+            .into_vec();
+
+        let natural = columns.iter().enumerate()
+            .map(|(i, c)| rows.iter()
+                .map(|r| r.get(i).map(|x| x.chars().count()).unwrap_or(0))
+                .chain(std::iter::once(if header { c.header.chars().count() } else { 0 }))
+                .max()
+                .unwrap_or(0))
+            .into_vec();
+
+        let widths = match WIDTH {
+            Some(w) => column_widths(&natural, w.saturating_sub(self.indent_level + 1)),
+            None => natural
+        };
+
+        if header {
+            let cells = columns.iter().map(|c| c.header.to_owned()).into_vec();
+            self.table_row(columns, &widths, &cells, true);
+        }
+
+        for r in &rows {
+            self.table_row(columns, &widths, r, false);
+        }
+    }
+
+    fn table_row(&mut self, columns: &[TableColumn], widths: &[usize], cells: &[String], header: bool) {
+        let mut line = String::new();
+
+        for (i, (c, w)) in columns.iter().zip(widths).enumerate() {
+            let cell = truncate(cells.get(i).map(|x| x.as_str()).unwrap_or(""), *w, c.truncate_start);
+            let len = cell.chars().count();
+
+            line += &if header { cell.bold().to_string() } else { cell };
+
+            // the last column isn't padded at all, so lines don't end in spaces
+            if i + 1 < columns.len() {
+                line += &" ".repeat(w - len + COLUMN_GAP);
+            }
+        }
+
+        self.preformatted(&line);
+    }
+}
+
+/// Narrows the widest columns until the table fits in `available` characters, if the columns can get that narrow.
+fn column_widths(natural: &[usize], available: usize) -> Vec<usize> {
+    let available = available.saturating_sub(COLUMN_GAP * natural.len().saturating_sub(1));
+
+    if natural.iter().sum::<usize>() <= available {
+        return natural.to_vec();
+    }
+
+    // the narrow columns keep their width, and whatever's left is split evenly between the rest
+    let mut order = (0..natural.len()).into_vec();
+    order.sort_by_key(|i| natural[*i]);
+
+    let mut ret = natural.to_vec();
+    let mut left = available;
+
+    for (n, i) in order.iter().enumerate() {
+        let share = left / (order.len() - n);
+
+        if natural[*i] <= share {
+            left -= natural[*i];
+            continue;
+        }
+
+        let rest = &order[n..];
+        let (share, extra) = (left / rest.len(), left % rest.len());
+
+        // the leftover characters go to the widest columns
+        for (m, j) in rest.iter().enumerate() {
+            let bonus = if m >= rest.len() - extra { 1 } else { 0 };
+            ret[*j] = max(min(natural[*j], share + bonus), min(natural[*j], MIN_COLUMN_WIDTH));
+        }
+
+        break;
+    }
+
+    ret
+}
+
+/// Cuts a string short with a '…' at its start or end so it's at most `width` characters.
+fn truncate(s: &str, width: usize, start: bool) -> String {
+    let len = s.chars().count();
+
+    if len <= width {
+        return s.to_owned();
+    }
+
+    if width == 0 {
+        return "".to_owned();
+    }
+
+    if start {
+        "…".to_owned() + &s.chars().skip(len - width + 1).collect::<String>()
+    }
+    else {
+        s.chars().take(width - 1).collect::<String>() + "…"
+    }
+}
+
+/// Writes to stdout as-is, exiting quietly if whatever it's piped into has stopped reading.
+///
+/// This is for output meant to be read by other programs, which shouldn't be wrapped or indented like the output of a PrintingContext.
+pub fn write_out(s: &str) {
+    let out = stdout();
+    let mut lock = out.lock();
+
+    if let Err(e) = lock.write_all(s.as_bytes()).and_then(|_| lock.flush()) {
+        if e.kind() == ErrorKind::BrokenPipe {
+            exit(0);
+        }
+
+        log().error(&format!("Failed to write the output: {}", e));
+        exit(1);
+    }
 }
 
 struct LoggingContext();
@@ -120,50 +287,54 @@ impl Logger for LoggingContext {
         eprintln!("{} {}", "[error]".bold().red(), s);
     }
 
-    fn cmdline(&mut self, cmdline: &str, mut index: usize, len: usize) {
-        let mut char_deque = cmdline.chars().collect::<VecDeque<_>>();
-        let end = || index + len;
+    fn cmdline(&mut self, cmdline: &str, index: usize, len: usize) {
+        let chars = cmdline.trim_end().chars().collect::<Vec<_>>();
+        // a span past the end (e.g. where a missing argument should go) still gets a caret
+        let len = max(len, 1);
+        let (mut start, mut end) = (0, max(chars.len(), index + len));
 
-        let print_top = || for (i, c) in char_deque.into_iter().enumerate() {
-            if i < index {
-                eprint!("{}", c);
-            }
-            else {
-                eprint!("{}", c.to_string().bold().red());
-            }
-            eprintln!();
-        };
-
-        let print_bottom = || for (i, _) in char_deque.into_iter().enumerate() {
-            if i < index {
-                eprint!(" ");
-            }
-            else {
-                eprint!("^");
+        // if the command line doesn't fit, show as much of it around the span as does
+        if let Some(w) = WIDTH {
+            if end - start > w {
+                start = min(index.saturating_sub(w / 4), end - w);
+                end = start + w;
             }
-            eprintln!();
-        };
+        }
 
         eprintln!();
-        if let Some(w) = WIDTH {
-            if len < w {
-                while char_deque.len() > w {
-                    if end() > char_deque.len() {
-                        char_deque.pop_front();
-                        index -= 1;
-                    }
-                    else {
-                        char_deque.pop_back();
-                    }
-                }
+
+        for i in start..end {
+            let c = chars.get(i).copied().unwrap_or(' ');
+
+            if i >= index && i < index + len {
+                eprint!("{}", c.to_string().bold().red());
             }
             else {
-                print_top();
-                return;
+                eprint!("{}", c);
             }
         }
+        eprintln!();
 
-        print_top();
-        print_bottom();
+        eprintln!("{}{}", " ".repeat(index - start), "^".repeat(min(len, end - index)).bold().red());
     }
 }
+
+#[test]
+fn test_column_widths() {
+    assert_eq!(column_widths(&[10, 5, 20], 100), vec![10, 5, 20]);
+    // the gaps take 4 of the 39 characters, leaving exactly enough
+    assert_eq!(column_widths(&[10, 5, 20], 39), vec![10, 5, 20]);
+    assert_eq!(column_widths(&[10, 5, 20], 35), vec![10, 5, 16]);
+    assert_eq!(column_widths(&[30, 5, 20], 35), vec![13, 5, 13]);
+    assert_eq!(column_widths(&[30, 5, 20], 36), vec![14, 5, 13]);
+    // too narrow for anything to fit, so the columns stop shrinking instead
+    assert_eq!(column_widths(&[30, 2, 20], 5), vec![4, 2, 4]);
+}
+
+#[test]
+fn test_truncate() {
+    assert_eq!(truncate("client", 6, false), "client");
+    assert_eq!(truncate("client", 4, false), "cli…");
+    assert_eq!(truncate("/a/b/notes.txt", 10, true), "…notes.txt");
+    assert_eq!(truncate("/a/b/notes.txt", 0, true), "");
+}
@@ -3,7 +3,34 @@ use std::ops::{Index, Range};
 use std::slice::Iter;
 use std::iter::{Peekable, Fuse};
 
-pub struct Args<'a> {
+/// A run of characters in the command line, counted in chars rather than bytes so it lines up with what's printed.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub index: usize,
+    pub len: usize,
+}
+
+impl Span {
+    /// Returns the span of the given bytes of an argument that starts at `arg_index` in the command line.
+    pub fn of(arg: &str, arg_index: usize, bytes: Range<usize>) -> Self {
+        let start = rendered_len(arg, bytes.start);
+
+        Span { index: arg_index + start, len: rendered_len(arg, bytes.end) - start }
+    }
+}
+
+/// Returns how many characters the first `byte_index` bytes of an argument take up once it's written into the command line by `Args::new`.
+pub fn rendered_len(arg: &str, byte_index: usize) -> usize {
+    let quoted = arg.contains(' ');
+
+    arg[..byte_index].chars().map(|c| match c {
+        '\n' => 2,
+        '"' if quoted => 2,
+        _ => 1
+    }).sum()
+}
+
+pub struct Args {
     args: Vec<(String, usize)>,
     cmdline: String
 }
@@ -27,23 +54,29 @@ impl Iterator for ArgsIter<'_> {
     type Item = (String, usize);
 
     fn next(&mut self) -> Option<Self::Item> {
+        let index = &mut self.index;
+
         self.iter.next().map(|x| {
-            self.index += 1;
-            x
+            *index += 1;
+            x.clone()
         })
     }
 }
 
 
 impl Args {
+    /// Joins the arguments into a printable command line.
+    ///
+    /// Each argument is paired with the char index in the command line where its text starts, which is just past the opening quote if it had to be quoted.
     pub fn new(a: &[&str]) -> Self {
-        let a: Vec<&str> = a.into_iter().collect();
         let mut cmdline = String::new();
+        let mut len = 0;
 
-        let aa = a.into_iter().map(|arg| {
-            let old_index = cmdline.len();
+        let aa = a.iter().map(|arg| {
+            let quoted = arg.contains(' ');
+            let index = len + if quoted { 1 } else { 0 };
 
-            if arg.contains(' ') {
+            if quoted {
                 let new_arg = arg.replace('"', "\\\"").replace('\n', "\\n");
                 cmdline.push('"');
                 cmdline += &new_arg;
@@ -54,8 +87,9 @@ impl Args {
                 cmdline += &new_arg;
             }
             cmdline.push(' ');
+            len = cmdline.chars().count();
 
-            (arg.to_owned(), old_index)
+            (arg.to_string(), index)
         }).into_vec();
 
         Args { args: aa, cmdline }
@@ -77,3 +111,17 @@ impl Index<usize> for Args {
         &self.args[arg]
     }
 }
+
+#[test]
+fn test_spans() {
+    let args = Args::new(&["meta", "get", "where", "a = \"b\" or", "c\nd"]);
+    assert_eq!(args.cmdline(), "meta get where \"a = \\\"b\\\" or\" c\\nd ");
+
+    let (arg, index) = &args[3];
+    let span = Span::of(arg, *index, 4..7);
+    assert_eq!(args.cmdline().chars().skip(span.index).take(span.len).collect::<String>(), "\\\"b\\\"");
+
+    let (arg, index) = &args[4];
+    let span = Span::of(arg, *index, 2..3);
+    assert_eq!(args.cmdline().chars().skip(span.index).take(span.len).collect::<String>(), "d");
+}
@@ -0,0 +1,42 @@
+use self::BuiltinKey::*;
+
+/// Keys starting with '@' that refer to an attribute of the entry itself instead of its metadata.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum BuiltinKey {
+    Name,
+    Path,
+    Ext,
+    Size,
+    Mtime,
+    Type,
+    Hash,
+}
+
+pub static BUILTIN_KEYS: &[(&str, BuiltinKey)] = &[
+    ("@name", Name),
+    ("@path", Path),
+    ("@ext", Ext),
+    ("@size", Size),
+    ("@mtime", Mtime),
+    ("@type", Type),
+    ("@hash", Hash),
+];
+
+pub fn is_builtin(key: &str) -> bool {
+    key.starts_with('@')
+}
+
+impl BuiltinKey {
+    pub fn from_key(key: &str) -> Option<BuiltinKey> {
+        BUILTIN_KEYS.iter()
+            .find(|x| x.0 == key)
+            .map(|x| x.1)
+    }
+
+    pub fn key(&self) -> &'static str {
+        BUILTIN_KEYS.iter()
+            .find(|x| x.1 == *self)
+            .map(|x| x.0)
+            .expect("Every BuiltinKey must be listed in BUILTIN_KEYS.")
+    }
+}
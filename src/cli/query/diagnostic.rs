@@ -0,0 +1,123 @@
+use colored::Colorize;
+use crate::cli::lang;
+use crate::cli::print::{log, Logger};
+use crate::cli::query::builtin::BUILTIN_KEYS;
+use crate::cli::query::lex::{HexSequenceError, LexError, StringLiteralLexError, QUERY_KEYWORDS};
+use crate::cli::query::lexeme::OwnedLexeme;
+use crate::cli::query::parse::{ParseError, Query};
+use crate::cli::typo::typos_threshold;
+use crate::linq::collectors::IntoVec;
+
+/// Returns a " Did you mean ...?" sentence if `token` looks like a misspelled query keyword or built-in key.
+pub fn did_you_mean(token: &str) -> String {
+    let words = QUERY_KEYWORDS.iter().copied().chain(BUILTIN_KEYS.iter().map(|x| x.0));
+    let typos = typos_threshold(token, words, 0.5, 3);
+
+    if typos.is_empty() {
+        return "".to_owned();
+    }
+
+    let words = typos.iter().map(|x| x.0.bold().yellow().to_string()).into_vec();
+    format!(" Did you mean {}?", lang::or(words.iter().map(|x| x.as_str())))
+}
+
+fn log_lexeme(l: &OwnedLexeme) {
+    log().cmdline(&l.cmdline, l.span.index, l.span.len);
+}
+
+/// Logs a query that failed to lex, pointing to where in the command line it went wrong.
+pub fn report_lex_error(e: &LexError, cmdline: &str) {
+    match e {
+        LexError::StringError(StringLiteralLexError::MissingClosingQuote, span) => {
+            log().error("This quoted value is missing its closing quote.");
+            log().cmdline(cmdline, span.index, span.len);
+        }
+        LexError::StringError(StringLiteralLexError::SyntaxError(_), span) => {
+            log().error("Unknown escape sequence. The escape sequences are \\n, \\t, \\r, \\0, \\b, \\f, \\/, \\\\, \\', \\\", \\xNN, \\uNNNN and \\u{N...}.");
+            log().cmdline(cmdline, span.index, span.len);
+        }
+        LexError::StringError(StringLiteralLexError::HexSequence(e, _), span) => {
+            log().error(match e {
+                HexSequenceError::NotEnoughChars => "This escape sequence is cut short. \\x takes two hex digits, \\u takes four, and \\u{...} takes one to six followed by a '}'.",
+                HexSequenceError::NonHexChar => "Expected a hex digit (0-9, a-f or A-F).",
+                HexSequenceError::NonUtf8Sequence => "This escape sequence does not stand for valid UTF-8 text. Consecutive \\xNN escapes must form a UTF-8 sequence, and \\u escapes must give a Unicode code point that isn't half of a surrogate pair."
+            });
+            log().cmdline(cmdline, span.index, span.len);
+        }
+        LexError::UnknownToken(a) => {
+            log().error(&format!("The token {} was unrecognized.{}", a.arg.bold().red(), did_you_mean(&a.arg)));
+            log().cmdline(cmdline, a.position, a.arg.chars().count());
+        }
+    }
+}
+
+/// Logs a query that failed to parse, pointing to where in the command line it went wrong.
+pub fn report_parse_error(e: &ParseError, cmdline: &str) {
+    match e {
+        ParseError::UnexpectedEOF(s) => {
+            log().error(s);
+            log().cmdline(cmdline, cmdline.trim_end().chars().count(), 1);
+        }
+        ParseError::UnexpectedToken((l, s)) => {
+            log().error(&format!("Unexpected {}. {}{}", l.token.bold().red(), s, did_you_mean(&l.token)));
+            log_lexeme(l);
+        }
+        ParseError::TrailingToken(l) => {
+            let hint = match did_you_mean(&l.token).as_str() {
+                "" => format!(" Most likely you forgot an {} or {} before it.", "and".bold().yellow(), "or".bold().yellow()),
+                s => s.to_owned()
+            };
+
+            log().error(&format!("Unexpected {} after the end of the query.{}", l.token.bold().red(), hint));
+            log_lexeme(l);
+        }
+    }
+}
+
+/// Returns the keys of the query that no entry has, each paired with the known keys it's plausibly a misspelling of.
+pub fn unknown_keys<'a, 'b>(query: &'a Query, known: &'b [String]) -> Vec<(&'a str, Vec<&'b str>)> {
+    query.keys().into_iter()
+        .filter(|k| !known.iter().any(|x| x == k))
+        .map(|k| (k, typos_threshold(k, known.iter().map(|x| x.as_str()), 0.5, 3).into_iter().map(|x| x.0).into_vec()))
+        .into_vec()
+}
+
+/// Logs each key of the query that no entry has, as an error if `strict` and as a warning otherwise.
+///
+/// Returns true if any key was unknown.
+pub fn report_unknown_keys(query: &Query, known: &[String], strict: bool) -> bool {
+    let unknown = unknown_keys(query, known);
+
+    for (key, typos) in &unknown {
+        let hint = if typos.is_empty() {
+            "".to_owned()
+        }
+        else {
+            let words = typos.iter().map(|x| x.bold().yellow().to_string()).into_vec();
+            format!(" Did you mean {}?", lang::or(words.iter().map(|x| x.as_str())))
+        };
+
+        let msg = format!("No file or directory has the key {}, so the query can't match on it.{}", key.bold().red(), hint);
+
+        if strict {
+            log().error(&msg);
+        }
+        else {
+            log().warn(&msg);
+        }
+    }
+
+    !unknown.is_empty()
+}
+
+#[test]
+fn test_unknown_keys() {
+    let known = ["author", "rating", "status"].iter().map(|x| x.to_string()).into_vec();
+    let query = crate::cli::query::parse::parse_query_text("autor = x and rating > 3 or staus or xyzzy order by @size").unwrap();
+
+    assert_eq!(unknown_keys(&query, &known), vec![
+        ("autor", vec!["author"]),
+        ("staus", vec!["status"]),
+        ("xyzzy", vec![]),
+    ]);
+}
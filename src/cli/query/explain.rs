@@ -0,0 +1,171 @@
+use std::process::exit;
+use colored::Colorize;
+use crate::cli::print::{log, print, Logger};
+use crate::cli::query::parse::{AndQuery, Factor, OrQuery, Query};
+use crate::linq::collectors::IntoVec;
+use crate::database::database::Database;
+
+/// A node of the query tree as it is printed.
+///
+/// Chains of 'and'/'or' are flattened into a single node so the grouping the parser chose is visible at a glance.
+struct ExplainNode {
+    label: String,
+    children: Vec<ExplainNode>,
+}
+
+impl ExplainNode {
+    fn leaf(label: String) -> Self {
+        ExplainNode { label, children: Vec::new() }
+    }
+
+    fn branch(label: &str, mut children: Vec<ExplainNode>) -> Self {
+        if children.len() == 1 {
+            return children.pop().unwrap();
+        }
+
+        ExplainNode { label: label.to_owned(), children }
+    }
+}
+
+fn or_node(query: &OrQuery) -> ExplainNode {
+    let mut children = vec![and_node(&query.and_query)];
+    let mut next = &query.next;
+
+    while let Some(q) = next {
+        children.push(and_node(&q.and_query));
+        next = &q.next;
+    }
+
+    ExplainNode::branch("or", children)
+}
+
+fn and_node(query: &AndQuery) -> ExplainNode {
+    let mut children = vec![factor_node(&query.factor)];
+    let mut next = &query.next;
+
+    while let Some(q) = next {
+        children.push(factor_node(&q.factor));
+        next = &q.next;
+    }
+
+    ExplainNode::branch("and", children)
+}
+
+fn factor_node(factor: &Factor) -> ExplainNode {
+    match factor {
+        // the tree already shows the grouping, so the parentheses themselves aren't needed
+        Factor::Query(q) => or_node(q),
+        Factor::Not(f) => ExplainNode { label: "not".to_owned(), children: vec![factor_node(f)] },
+        Factor::Key(k) => ExplainNode::leaf(format!("{} exists", k)),
+        Factor::Scoped((s, f)) => match &**f {
+            Factor::Key(k) => ExplainNode::leaf(format!("{}.{} exists", s, k)),
+            _ => ExplainNode::leaf(factor.to_string())
+        },
+        _ => ExplainNode::leaf(factor.to_string()),
+    }
+}
+
+fn render(node: &ExplainNode, prefix: &str, child_prefix: &str, out: &mut Vec<String>) {
+    out.push(format!("{}{}", prefix, node.label));
+
+    for (i, child) in node.children.iter().enumerate() {
+        if i + 1 == node.children.len() {
+            render(child, &format!("{}└── ", child_prefix), &format!("{}    ", child_prefix), out);
+        }
+        else {
+            render(child, &format!("{}├── ", child_prefix), &format!("{}│   ", child_prefix), out);
+        }
+    }
+}
+
+/// Returns the lines of a tree diagram of the query, with every 'and', 'or' and 'not' shown as a node above what it applies to.
+///
+/// Any 'order by', 'limit' and 'offset' follow the tree on lines of their own, as does whether metadata is inherited.
+pub fn query_tree(query: &Query) -> Vec<String> {
+    let mut ret = Vec::new();
+    render(&or_node(&query.filter), "", "", &mut ret);
+
+    if !query.order_by.is_empty() {
+        ret.push(format!("order by {}", query.order_by.iter().map(|k| k.to_string()).into_vec().join(", ")));
+    }
+
+    if let Some(n) = query.limit {
+        ret.push(format!("limit {}", n));
+    }
+
+    if let Some(n) = query.offset {
+        ret.push(format!("offset {}", n));
+    }
+
+    if query.inherited {
+        ret.push("with metadata inherited from the directories above each entry".to_owned());
+    }
+
+    ret
+}
+
+/// Prints how a query was parsed, the SQL it compiles to, and how the database plans to run it.
+pub fn print_explanation<'a, E: std::fmt::Display, D: Database<'a, E>>(db: &D, query: &Query) {
+    let explanation = match db.explain_query(query) {
+        Ok(e) => e,
+        Err(e) => {
+            log().error(&format!("Failed to explain the query: {}", e));
+            exit(1);
+        }
+    };
+
+    let mut p = print();
+
+    p.line(&"QUERY:".bold().to_string());
+    for line in query_tree(query) {
+        p.preformatted(&line);
+    }
+    p.newline();
+
+    p.line(&"SQL:".bold().to_string());
+    p.preformatted(&explanation.sql);
+    p.newline();
+
+    p.line(&"PLAN:".bold().to_string());
+    for line in &explanation.plan {
+        p.preformatted(line);
+    }
+}
+
+#[test]
+fn test_query_tree() {
+    use crate::cli::query::parse::parse_query_text;
+
+    let query = parse_query_text("status = done and (not reviewed or priority > 2) order by priority desc limit 5").ok().unwrap();
+
+    assert_eq!(query_tree(&query), vec![
+        "and",
+        "├── status = \"done\"",
+        "└── or",
+        "    ├── not",
+        "    │   └── reviewed exists",
+        "    └── priority > \"2\"",
+        "order by priority desc",
+        "limit 5",
+    ]);
+}
+
+#[test]
+fn test_explain_query() {
+    use crate::cli::query::parse::parse_query_text;
+    use crate::database::sqlite::SqliteDatabase;
+
+    let db = SqliteDatabase::new(":memory:").ok().expect("Failed to open an in-memory database.");
+    let query = parse_query_text("status = done").ok().unwrap();
+    let explanation = db.explain_query(&query).ok().expect("Failed to explain the query.");
+
+    assert_eq!(explanation.sql, concat!(
+        "SELECT 1, Directories.id, NULL, Directories.path, NULL FROM Directories ",
+        "WHERE EXISTS (SELECT 1 FROM DirectoryMetadata m WHERE m.directory_id = Directories.id AND m.key = ? AND m.value = ?) ",
+        "UNION ALL ",
+        "SELECT 0, Files.id, Files.directory_id, Files.filename, Files.hash FROM Files ",
+        "WHERE EXISTS (SELECT 1 FROM FileMetadata m WHERE m.file_id = Files.id AND m.key = ? AND m.value = ?) ",
+        "-- binds: [\"status\", \"done\", \"status\", \"done\"]"
+    ));
+    assert!(!explanation.plan.is_empty());
+}
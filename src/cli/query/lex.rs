@@ -1,5 +1,8 @@
 use fancy_regex::Regex;
 use std::convert::TryFrom;
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::CharIndices;
 
 use crate::format::re::regex_expect;
 use crate::format::str::{ToCharIterator, StrExtensions};
@@ -9,66 +12,258 @@ use super::lexeme::{LexemeQueue, LexemeKind, Lexeme};
 use self::LexError::*;
 use self::StringLiteralLexError::*;
 use self::HexSequenceError::*;
-use crate::cli::query::args::ArgsIter;
+use crate::cli::query::args::{ArgsIter, Span};
 use crate::cli::query::lexeme::EqualityKind;
 use crate::cli::args::ArgError;
 
 pub enum LexError {
-    StringError(StringLiteralLexError),
+    StringError(StringLiteralLexError, Span),
     UnknownToken(ArgError),
 }
 
+/// Why `get_token` couldn't find a token, before it's known where in the command line that was.
+enum TokenError {
+    StringError(StringLiteralLexError),
+    Unrecognized,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum StringLiteralLexError {
     MissingClosingQuote,
-    SyntaxError(usize)
+    /// An unknown escape sequence, given as the bytes of the literal it covers.
+    SyntaxError(Range<usize>),
+    /// A malformed \x or \u escape, given with the bytes of the literal it covers.
+    HexSequence(HexSequenceError, Range<usize>),
 }
 
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum HexSequenceError {
     NotEnoughChars,
     NonHexChar,
     NonUtf8Sequence
 }
 
-fn lex_string_literal(slice: &str) -> Result<usize, StringLiteralLexError> {
-    static QUOTE_REGEX: &Regex = regex_expect(r#"^("|').*?(?<!\)\1"#);
+type LiteralChars<'a> = Peekable<CharIndices<'a>>;
+
+/// Reads up to `max` hex digits, stopping early at a '}' if `braced`.
+///
+/// `escape_start` is where the escape sequence began, for reporting the whole sequence if it's cut short.
+fn hex_digits(slice: &str, chars: &mut LiteralChars, quote: char, escape_start: usize, min: usize, max: usize, braced: bool) -> Result<u32, StringLiteralLexError> {
+    let mut ret = 0u32;
+    let mut count = 0;
+
+    while count < max {
+        let (i, c) = match chars.peek() {
+            Some(x) => *x,
+            None => return Err(HexSequence(NotEnoughChars, escape_start..slice.len()))
+        };
+
+        if braced && c == '}' {
+            break;
+        }
+
+        match c.to_digit(16) {
+            Some(d) => {
+                chars.next();
+                ret = ret * 16 + d;
+                count += 1;
+            }
+            None if c == quote => return Err(HexSequence(NotEnoughChars, escape_start..i)),
+            None => return Err(HexSequence(NonHexChar, i..i + c.len_utf8()))
+        }
+    }
+
+    if count < min {
+        let end = chars.peek().map(|x| x.0).unwrap_or(slice.len());
+        return Err(HexSequence(NotEnoughChars, escape_start..end));
+    }
+
+    Ok(ret)
+}
+
+/// Reads the code point of a \u escape, whose "\u" starts at `escape_start` and has already been read.
+fn unicode_escape(slice: &str, chars: &mut LiteralChars, quote: char, escape_start: usize) -> Result<char, StringLiteralLexError> {
+    let end = |chars: &mut LiteralChars| chars.peek().map(|x| x.0).unwrap_or(slice.len());
 
-    let quot = match QUOTE_REGEX.find(slice)
-        .expect("QUOTE_REGEX is invalid") {
-        Some(s) => s,
-        None => return Err(MissingClosingQuote)
+    // \u{1F600}
+    if let Some((_, '{')) = chars.peek() {
+        chars.next();
+        let cp = hex_digits(slice, chars, quote, escape_start, 1, 6, true)?;
+
+        match chars.next() {
+            Some((_, '}')) => {}
+            // more than six digits is always past the last code point
+            Some((i, c)) if c.is_ascii_hexdigit() => return Err(HexSequence(NonUtf8Sequence, escape_start..i + 1)),
+            Some((i, c)) if c != quote => return Err(HexSequence(NonHexChar, i..i + c.len_utf8())),
+            _ => return Err(HexSequence(NotEnoughChars, escape_start..end(chars)))
+        }
+
+        return std::char::from_u32(cp).ok_or(HexSequence(NonUtf8Sequence, escape_start..end(chars)));
+    }
+
+    // \u00e9, or a surrogate pair like \ud83d\ude00 as JSON writes them
+    let cp = hex_digits(slice, chars, quote, escape_start, 4, 4, false)?;
+
+    if (0xD800..0xDC00).contains(&cp) {
+        let low_start = end(chars);
+
+        if slice[low_start..].starts_with("\\u") {
+            chars.next();
+            chars.next();
+            let low = hex_digits(slice, chars, quote, low_start, 4, 4, false)?;
+
+            if (0xDC00..0xE000).contains(&low) {
+                let cp = 0x10000 + ((cp - 0xD800) << 10) + (low - 0xDC00);
+                return std::char::from_u32(cp).ok_or(HexSequence(NonUtf8Sequence, escape_start..end(chars)));
+            }
+        }
+
+        return Err(HexSequence(NonUtf8Sequence, escape_start..end(chars)));
+    }
+
+    std::char::from_u32(cp).ok_or(HexSequence(NonUtf8Sequence, escape_start..end(chars)))
+}
+
+/// Scans the quoted value at the start of `slice`, which may use either single or double quotes.
+///
+/// Returns the length of the quoted value in bytes and the string it stands for.
+///
+/// Besides \n, \t, \r, \0, \b, \f, \/, \\, \' and \", a value can contain \xNN, \uNNNN and \u{N...}.
+/// Consecutive \xNN escapes give the bytes of a UTF-8 sequence, so "\xc3\xa9" is "é".
+pub fn scan_string_literal(slice: &str) -> Result<(usize, String), StringLiteralLexError> {
+    let mut chars = slice.char_indices().peekable();
+    let quote = match chars.next() {
+        Some((_, c)) if c == '"' || c == '\'' => c,
+        _ => return Err(SyntaxError(0..0))
     };
 
-    if quot.start() != 0 {
-        return Err(SyntaxError(0))
+    let mut ret = String::new();
+    // bytes from a run of \xNN escapes, along with where the run started
+    let mut bytes = Vec::<u8>::new();
+    let mut bytes_start = 0;
+
+    loop {
+        let (i, c) = match chars.next() {
+            Some(x) => x,
+            None => return Err(MissingClosingQuote)
+        };
+
+        if c == '\\' && chars.peek().map(|x| x.1) == Some('x') {
+            chars.next();
+            if bytes.is_empty() {
+                bytes_start = i;
+            }
+            bytes.push(hex_digits(slice, &mut chars, quote, i, 2, 2, false)? as u8);
+            continue;
+        }
+
+        if !bytes.is_empty() {
+            match std::str::from_utf8(&bytes) {
+                Ok(s) => ret += s,
+                Err(_) => return Err(HexSequence(NonUtf8Sequence, bytes_start..i))
+            }
+            bytes.clear();
+        }
+
+        if c == quote {
+            return Ok((i + c.len_utf8(), ret));
+        }
+
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+
+        let (j, e) = match chars.next() {
+            Some(x) => x,
+            None => return Err(MissingClosingQuote)
+        };
+
+        match e {
+            'n' => ret.push('\n'),
+            't' => ret.push('\t'),
+            'r' => ret.push('\r'),
+            '0' => ret.push('\0'),
+            'b' => ret.push('\u{8}'),
+            'f' => ret.push('\u{c}'),
+            '\\' | '\'' | '"' | '/' => ret.push(e),
+            'u' => ret.push(unicode_escape(slice, &mut chars, quote, i)?),
+            _ => return Err(SyntaxError(i..j + e.len_utf8()))
+        }
     }
+}
 
-    match serde_json::from_str(&slice[0..quot.end()]) {
-        Ok(s) => Ok(quot.end()),
-        Err(e) => Err(SyntaxError(e.column()))
+/// Returns the contents of a value token with its surrounding quotes removed and its escape sequences resolved.
+///
+/// Unquoted tokens (e.g. a bare key used as a value) are returned unchanged.
+pub fn unquote(token: &str) -> String {
+    if token.starts_with('"') || token.starts_with('\'') {
+        if let Ok((_, s)) = scan_string_literal(token) {
+            return s;
+        }
     }
+
+    token.to_owned()
 }
 
-static ID_REGEX: &Regex = regex_expect(r"^[a-zA-Z0-9\-_]+\b");
+// '*' and '?' are for the key patterns after 'any' and 'all'
+// a '.' can't start one, so that 'parent.status' is 'parent', '.' and 'status' while bare values like 1.5 stay whole
+static ID_REGEX: &Regex = regex_expect(r"^@?[a-zA-Z0-9\-_*?][a-zA-Z0-9\-_*?.]*");
 
 
+// keywords ignore case, and aren't matched at the start of a longer key like 'in-progress' or 'in.txt'
+// except for 'parent' and 'ancestor', which a '.' follows in a scope like 'parent.status'
 static LITERAL_TOKENS: &[(&Regex, LexemeKind)] = &[
     (regex_expect(r"^,"), LexemeKind::Comma),
     (regex_expect(r"^\("), LexemeKind::LParen),
     (regex_expect(r"^\)"), LexemeKind::RParen),
+    (regex_expect(r"^\."), LexemeKind::Dot),
+    (regex_expect(r"^&&"), LexemeKind::And),
+    (regex_expect(r"^\|\|"), LexemeKind::Or),
+    (regex_expect(r"^!="), LexemeKind::Equals(EqualityKind::NotEqual)),
+    (regex_expect(r"^!"), LexemeKind::Not),
+    (regex_expect(r"^<="), LexemeKind::Equals(EqualityKind::LessEqual)),
+    (regex_expect(r"^>="), LexemeKind::Equals(EqualityKind::GreaterEqual)),
+    (regex_expect(r"^<"), LexemeKind::Equals(EqualityKind::Less)),
+    (regex_expect(r"^>"), LexemeKind::Equals(EqualityKind::Greater)),
     (regex_expect(r"^=="), LexemeKind::Equals(EqualityKind::Strict)),
     (regex_expect(r"^="), LexemeKind::Equals(EqualityKind::Strict)),
-    (regex_expect(r"^is\b"), LexemeKind::Equals(EqualityKind::Strict)),
-    (regex_expect(r"^in\b"), LexemeKind::In),
-    (regex_expect(r"^and\b"), LexemeKind::And),
-    (regex_expect(r"^or\b"), LexemeKind::Or),
-    (regex_expect(r"^matches\b"), LexemeKind::Equals(EqualityKind::Matches))
+    (regex_expect(r"(?i)^is(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Equals(EqualityKind::Strict)),
+    (regex_expect(r"(?i)^in(?![a-zA-Z0-9\-_*?.])"), LexemeKind::In),
+    (regex_expect(r"(?i)^under(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Under),
+    (regex_expect(r"(?i)^directly(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Directly),
+    (regex_expect(r"(?i)^any(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Any),
+    (regex_expect(r"(?i)^all(?![a-zA-Z0-9\-_*?.])"), LexemeKind::All),
+    (regex_expect(r"(?i)^near(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Near),
+    (regex_expect(r"(?i)^within(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Within),
+    (regex_expect(r"(?i)^parent(?![a-zA-Z0-9\-_*?])"), LexemeKind::Parent),
+    (regex_expect(r"(?i)^ancestor(?![a-zA-Z0-9\-_*?])"), LexemeKind::Ancestor),
+    (regex_expect(r"(?i)^has(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Has),
+    (regex_expect(r"(?i)^and(?![a-zA-Z0-9\-_*?.])"), LexemeKind::And),
+    (regex_expect(r"(?i)^or(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Or),
+    (regex_expect(r"(?i)^not(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Not),
+    (regex_expect(r"(?i)^matches(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Equals(EqualityKind::Matches)),
+    (regex_expect(r"(?i)^imatches(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Equals(EqualityKind::MatchesIgnoreCase)),
+    (regex_expect(r"(?i)^like(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Equals(EqualityKind::Like)),
+    (regex_expect(r"(?i)^glob(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Equals(EqualityKind::Glob)),
+    (regex_expect(r"(?i)^order(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Order),
+    (regex_expect(r"(?i)^by(?![a-zA-Z0-9\-_*?.])"), LexemeKind::By),
+    (regex_expect(r"(?i)^asc(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Asc),
+    (regex_expect(r"(?i)^desc(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Desc),
+    (regex_expect(r"(?i)^limit(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Limit),
+    (regex_expect(r"(?i)^offset(?![a-zA-Z0-9\-_*?.])"), LexemeKind::Offset),
+    (regex_expect(r"^@q:[a-zA-Z0-9\-_]+(?![a-zA-Z0-9\-_*?.])"), LexemeKind::SavedQuery),
+    (regex_expect(r"^\$@?[a-zA-Z0-9\-_]+"), LexemeKind::KeyRef)
 ];
 
-fn get_token(slice: &str) -> Result<(usize, LexemeKind), LexError> {
+/// The words with a meaning of their own in a query, for suggesting corrections to misspelled ones.
+pub static QUERY_KEYWORDS: &[&str] = &["and", "or", "not", "in", "is", "under", "directly", "any", "all", "matches", "imatches", "like", "glob", "near", "within", "parent", "ancestor", "has", "order", "by", "asc", "desc", "limit", "offset"];
+
+fn get_token(slice: &str) -> Result<(usize, LexemeKind), TokenError> {
     let slice = slice.trim_start();
 
     for (re, kind) in LITERAL_TOKENS {
-        if let Some(m) = re.find(slice) {
+        if let Some(m) = re.find(slice).expect("LITERAL_TOKENS contains an invalid regex") {
             debug_assert_eq!(m.start(), 0);
 
             return Ok((m.end(), *kind));
@@ -76,46 +271,397 @@ fn get_token(slice: &str) -> Result<(usize, LexemeKind), LexError> {
     }
 
     if slice.starts_with("'") || slice.starts_with("\"") {
-        return match lex_string_literal(slice) {
-            Ok(s) => Ok((s, LexemeKind::Value)),
-            Err(e) => Err(LexError::StringError(e))
+        return match scan_string_literal(slice) {
+            Ok((len, _)) => Ok((len, LexemeKind::Value)),
+            Err(e) => Err(TokenError::StringError(e))
         };
     }
 
-    if let Some(m) = ID_REGEX.find(slice) {
+    if let Some(m) = ID_REGEX.find(slice).expect("ID_REGEX is invalid") {
         debug_assert_eq!(m.start(), 0);
 
         return Ok ((m.end(), LexemeKind::Key));
     }
 
-    Err(NonLexableSequence)
+    Err(TokenError::Unrecognized)
 }
 
-pub fn lex<'a, 'b, I: Iterator<Item=(String, usize)>>(args: I, cmdline: &'b str) -> Result<LexemeQueue<'a, 'b>, LexError> {
-    let a = args.flat_map(|(arg, index)| {
-        if arg.contains(" ") {
-            [("(", index), (arg, index), (")", index + arg.len() - 1)]
+/// Splits the arguments of a query into lexemes, each knowing where it came from in `cmdline`.
+///
+/// Each argument is paired with the char index in `cmdline` where its text starts, as given by `Args`.
+///
+/// When a query is spread over several arguments, the shell has already removed any quotes around them, so an argument
+/// that follows an operator is taken whole as its value, even if it looks like a keyword or has parentheses in it, unless it's
+/// a single key, value or $key. Any other argument with spaces is treated as being in parentheses. A query given as a single
+/// argument is lexed as-is.
+pub fn lex<'a, 'b>(args: &'a [(String, usize)], cmdline: &'b str) -> Result<LexemeQueue<'a, 'b>, LexError> {
+    let spread = args.len() > 1;
+
+    let mut ret = LexemeQueue::new();
+    let mut prev = Vec::<LexemeKind>::new();
+
+    for (arg, index) in args {
+        let mut lexemes = Vec::new();
+        let lexed = lex_arg(arg, *index, cmdline, &mut lexemes);
+
+        if spread && takes_value(&prev) {
+            let single_value = lexed.is_ok() && lexemes.len() == 1 && matches!(lexemes[0].kind(), LexemeKind::Key | LexemeKind::Value | LexemeKind::KeyRef);
+            // 'is not' is lexed as 'is' followed by 'not'
+            let is_not = lexed.is_ok() && lexemes.len() == 1 && lexemes[0].kind() == LexemeKind::Not && prev.last() == Some(&LexemeKind::Equals(EqualityKind::Strict));
+
+            if !single_value && !is_not {
+                ret.push(Lexeme::new(arg, LexemeKind::Argument, cmdline, Span::of(arg, *index, 0..arg.len())));
+                prev.push(LexemeKind::Argument);
+                continue;
+            }
         }
-        else {
-            [(arg, index)]
+
+        lexed?;
+
+        let parens = spread && arg.contains(char::is_whitespace);
+
+        if parens {
+            ret.push(Lexeme::new("(", LexemeKind::LParen, cmdline, Span::of(arg, *index, 0..0)));
         }
-    }).collect::<Vec<(String, usize)>>();
 
-    let mut ret = LexemeQueue::new();
+        for l in lexemes {
+            prev.push(l.kind());
+            ret.push(l);
+        }
+
+        if parens {
+            ret.push(Lexeme::new(")", LexemeKind::RParen, cmdline, Span::of(arg, *index, arg.len()..arg.len())));
+            prev.push(LexemeKind::RParen);
+        }
+    }
 
-    for (arg, index) in &a {
-        let slice = arg.trim();
+    Ok(ret)
+}
+
+/// Returns true if the lexemes so far end with an operator that's followed by a value.
+fn takes_value(prev: &[LexemeKind]) -> bool {
+    match prev {
+        [.., LexemeKind::Directly, LexemeKind::In] => true,
+        [.., LexemeKind::Equals(EqualityKind::Strict), LexemeKind::Not] => true,
+        [.., LexemeKind::Equals(_)] | [.., LexemeKind::Near] | [.., LexemeKind::Under] => true,
+        _ => false
+    }
+}
+
+/// Splits a single argument into lexemes, appending them to `out`.
+fn lex_arg<'a, 'b>(arg: &'a str, index: usize, cmdline: &'b str, out: &mut Vec<Lexeme<'a, 'b>>) -> Result<(), LexError> {
+    let mut pos = 0;
+
+    loop {
+        let rest = &arg[pos..];
+        let trimmed = rest.trim_start();
+        if trimmed.is_empty() {
+            return Ok(());
+        }
 
-        while slice.len() > 0 {
-            let tup = get_token(slice)?;
-            ret.push(Lexeme::new(
-                &slice[..tup.0],
-                tup.1,
+        let start = pos + rest.len() - trimmed.len();
+
+        let (len, kind) = match get_token(trimmed) {
+            Ok(t) => t,
+            Err(TokenError::StringError(e)) => {
+                // the error's bytes are counted from the opening quote
+                let bytes = match &e {
+                    SyntaxError(r) | HexSequence(_, r) => start + r.start..start + r.end,
+                    MissingClosingQuote => start..arg.len()
+                };
+
+                return Err(LexError::StringError(e, Span::of(arg, index, bytes)));
+            }
+            Err(TokenError::Unrecognized) => {
+                let end = start + trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+                let span = Span::of(arg, index, start..end);
+
+                return Err(LexError::UnknownToken(ArgError::new(arg[start..end].to_owned(), span.index, cmdline)));
+            }
+        };
+
+        out.push(Lexeme::new(
+            &arg[start..start + len],
+            kind,
             cmdline,
-            index + tup.0
-            ));
+            Span::of(arg, index, start..start + len)
+        ));
+
+        pos = start + len;
+    }
+}
+
+#[test]
+fn test_lex_spans() {
+    use crate::cli::query::args::Args;
+
+    let args = Args::new(&["meta", "get", "where", "a = 'x y'", "and", "@size>=10"]);
+    let query = (3..6).map(|i| args[i].clone()).into_vec();
+
+    let mut lexemes = match lex(&query, args.cmdline()) {
+        Ok(l) => l,
+        Err(_) => panic!("Failed to lex {}", args.cmdline())
+    };
+
+    let mut tokens = Vec::new();
+    while let Some(l) = lexemes.pop() {
+        let span = l.span();
+        tokens.push((l.token().to_owned(), args.cmdline().chars().skip(span.index).take(span.len).collect::<String>()));
+    }
+
+    assert_eq!(tokens, vec![
+        ("(".to_owned(), "".to_owned()),
+        ("a".to_owned(), "a".to_owned()),
+        ("=".to_owned(), "=".to_owned()),
+        ("'x y'".to_owned(), "'x y'".to_owned()),
+        (")".to_owned(), "".to_owned()),
+        ("and".to_owned(), "and".to_owned()),
+        ("@size".to_owned(), "@size".to_owned()),
+        (">=".to_owned(), ">=".to_owned()),
+        ("10".to_owned(), "10".to_owned()),
+    ]);
+}
+
+#[test]
+fn test_lex_aliases() {
+    let kinds = |s: &str| {
+        let args = vec![(s.to_owned(), 0)];
+        let mut lexemes = match lex(&args, s) {
+            Ok(l) => l,
+            Err(_) => panic!("Failed to lex {}", s)
+        };
+
+        let mut ret = Vec::new();
+        while let Some(l) = lexemes.pop() {
+            ret.push(l.kind());
         }
+        ret
+    };
+
+    assert_eq!(kinds("a && b || !c"), kinds("a and b or not c"));
+    assert_eq!(kinds("a AND b Or NOT c"), kinds("a and b or not c"));
+    assert_eq!(kinds("a!=b"), vec![LexemeKind::Key, LexemeKind::Equals(EqualityKind::NotEqual), LexemeKind::Key]);
+    assert_eq!(kinds("!a"), vec![LexemeKind::Not, LexemeKind::Key]);
+    assert_eq!(kinds("in-progress"), vec![LexemeKind::Key]);
+    assert_eq!(kinds("order_id orders"), vec![LexemeKind::Key, LexemeKind::Key]);
+    assert_eq!(kinds("IN in In"), vec![LexemeKind::In, LexemeKind::In, LexemeKind::In]);
+}
+
+#[test]
+fn test_scan_string_literal() {
+    let ok = |s: &str, len: usize, v: &str| assert_eq!(scan_string_literal(s), Ok((len, v.to_owned())), "{}", s);
+    ok(r#""abc" rest"#, 5, "abc");
+    ok(r#"'it\'s'"#, 7, "it's");
+    ok(r#"'say "hi"'"#, 10, "say \"hi\"");
+    ok(r#""a\\""#, 5, "a\\");
+    ok(r#""\n\t\r\0\b\f\/""#, 16, "\n\t\r\0\u{8}\u{c}/");
+    ok(r#""\x41\xc3\xa9!""#, 15, "Aé!");
+    ok(r#""\u00e9\u{1F600}\ud83d\ude00""#, 29, "é😀😀");
+    ok("'ü'", 4, "ü");
+    let err = |s: &str, e: StringLiteralLexError| assert_eq!(scan_string_literal(s), Err(e), "{}", s);
+    err(r#""abc"#, MissingClosingQuote);
+    err(r#""abc\""#, MissingClosingQuote);
+    err(r#""a\qb""#, SyntaxError(2..4));
+    err(r#""\x4""#, HexSequence(NotEnoughChars, 1..4));
+    err(r#""\x4g""#, HexSequence(NonHexChar, 4..5));
+    err(r#""\xc3(""#, HexSequence(NonUtf8Sequence, 1..5));
+    err(r#""\xff""#, HexSequence(NonUtf8Sequence, 1..5));
+    err(r#""\u{110000}""#, HexSequence(NonUtf8Sequence, 1..11));
+    err(r#""\u{1234567}""#, HexSequence(NonUtf8Sequence, 1..11));
+    err(r#""\u{}""#, HexSequence(NotEnoughChars, 1..4));
+    err(r#""\u{12""#, HexSequence(NotEnoughChars, 1..6));
+    err(r#""\ud83d""#, HexSequence(NonUtf8Sequence, 1..7));
+    err(r#""\u12""#, HexSequence(NotEnoughChars, 1..5));
+    // round trip with serde_json's quoting
+    for s in &["quote \" backslash \\ newline \n tab \t", "\u{1}\u{1f}", "ünï ☺ 😀", ""] {
+        let q = serde_json::to_string(s).unwrap();
+        assert_eq!(scan_string_literal(&q), Ok((q.len(), s.to_string())));
     }
+}
 
-    Ok(ret)
-}
\ No newline at end of file
+#[test]
+fn test_lex_hyphenated_keywords() {
+    use crate::cli::query::args::Args;
+
+    let args = Args::new(&["meta", "get", "where", "order-id > 5 order by by-date desc limit 3 offset offset-count"]);
+    let query = vec![args[3].clone()];
+
+    let mut lexemes = match lex(&query, args.cmdline()) {
+        Ok(l) => l,
+        Err(_) => panic!("Failed to lex {}", args.cmdline())
+    };
+
+    let mut kinds = Vec::new();
+    while let Some(l) = lexemes.pop() {
+        kinds.push((l.token().to_owned(), l.kind()));
+    }
+
+    assert_eq!(kinds, vec![
+        ("order-id".to_owned(), LexemeKind::Key),
+        (">".to_owned(), LexemeKind::Equals(EqualityKind::Greater)),
+        ("5".to_owned(), LexemeKind::Key),
+        ("order".to_owned(), LexemeKind::Order),
+        ("by".to_owned(), LexemeKind::By),
+        ("by-date".to_owned(), LexemeKind::Key),
+        ("desc".to_owned(), LexemeKind::Desc),
+        ("limit".to_owned(), LexemeKind::Limit),
+        ("3".to_owned(), LexemeKind::Key),
+        ("offset".to_owned(), LexemeKind::Offset),
+        ("offset-count".to_owned(), LexemeKind::Key),
+    ]);
+}
+
+#[test]
+fn test_lex_key_patterns_after_keywords() {
+    use crate::cli::query::args::Args;
+
+    let args = Args::new(&["meta", "get", "where", "all-reviewers = 2 or any tag-* = x or all* = y order by any?"]);
+    let query = vec![args[3].clone()];
+
+    let mut lexemes = match lex(&query, args.cmdline()) {
+        Ok(l) => l,
+        Err(_) => panic!("Failed to lex {}", args.cmdline())
+    };
+
+    let mut kinds = Vec::new();
+    while let Some(l) = lexemes.pop() {
+        kinds.push((l.token().to_owned(), l.kind()));
+    }
+
+    assert_eq!(kinds, vec![
+        ("all-reviewers".to_owned(), LexemeKind::Key),
+        ("=".to_owned(), LexemeKind::Equals(EqualityKind::Strict)),
+        ("2".to_owned(), LexemeKind::Key),
+        ("or".to_owned(), LexemeKind::Or),
+        ("any".to_owned(), LexemeKind::Any),
+        ("tag-*".to_owned(), LexemeKind::Key),
+        ("=".to_owned(), LexemeKind::Equals(EqualityKind::Strict)),
+        ("x".to_owned(), LexemeKind::Key),
+        ("or".to_owned(), LexemeKind::Or),
+        ("all*".to_owned(), LexemeKind::Key),
+        ("=".to_owned(), LexemeKind::Equals(EqualityKind::Strict)),
+        ("y".to_owned(), LexemeKind::Key),
+        ("order".to_owned(), LexemeKind::Order),
+        ("by".to_owned(), LexemeKind::By),
+        ("any?".to_owned(), LexemeKind::Key),
+    ]);
+}
+
+#[test]
+fn test_lex_near_within_keys() {
+    use crate::cli::query::args::Args;
+
+    let args = Args::new(&["meta", "get", "where", "near-term near alpha within within-2"]);
+    let query = vec![args[3].clone()];
+
+    let mut lexemes = match lex(&query, args.cmdline()) {
+        Ok(l) => l,
+        Err(_) => panic!("Failed to lex {}", args.cmdline())
+    };
+
+    let mut kinds = Vec::new();
+    while let Some(l) = lexemes.pop() {
+        kinds.push((l.token().to_owned(), l.kind()));
+    }
+
+    assert_eq!(kinds, vec![
+        ("near-term".to_owned(), LexemeKind::Key),
+        ("near".to_owned(), LexemeKind::Near),
+        ("alpha".to_owned(), LexemeKind::Key),
+        ("within".to_owned(), LexemeKind::Within),
+        ("within-2".to_owned(), LexemeKind::Key),
+    ]);
+}
+
+#[test]
+fn test_lex_dotted_keys_after_keywords() {
+    let kinds = |s: &str| {
+        let args = vec![(s.to_owned(), 0)];
+        let mut lexemes = match lex(&args, s) {
+            Ok(l) => l,
+            Err(_) => panic!("Failed to lex {}", s)
+        };
+
+        let mut ret = Vec::new();
+        while let Some(l) = lexemes.pop() {
+            ret.push((l.token().to_owned(), l.kind()));
+        }
+        ret
+    };
+
+    assert_eq!(kinds("@name = in.txt or not.done or.x"), vec![
+        ("@name".to_owned(), LexemeKind::Key),
+        ("=".to_owned(), LexemeKind::Equals(EqualityKind::Strict)),
+        ("in.txt".to_owned(), LexemeKind::Key),
+        ("or".to_owned(), LexemeKind::Or),
+        ("not.done".to_owned(), LexemeKind::Key),
+        ("or.x".to_owned(), LexemeKind::Key),
+    ]);
+    assert_eq!(kinds("parent.status"), vec![
+        ("parent".to_owned(), LexemeKind::Parent),
+        (".".to_owned(), LexemeKind::Dot),
+        ("status".to_owned(), LexemeKind::Key),
+    ]);
+}
+
+#[test]
+fn test_lex_saved_query_names() {
+    let kinds = |s: &str| {
+        let args = vec![(s.to_owned(), 0)];
+        let mut lexemes = match lex(&args, s) {
+            Ok(l) => l,
+            Err(_) => panic!("Failed to lex {}", s)
+        };
+
+        let mut ret = Vec::new();
+        while let Some(l) = lexemes.pop() {
+            ret.push((l.token().to_owned(), l.kind()));
+        }
+        ret
+    };
+
+    assert_eq!(kinds("@q:foo- or @q:a_b-2 and @q:x"), vec![
+        ("@q:foo-".to_owned(), LexemeKind::SavedQuery),
+        ("or".to_owned(), LexemeKind::Or),
+        ("@q:a_b-2".to_owned(), LexemeKind::SavedQuery),
+        ("and".to_owned(), LexemeKind::And),
+        ("@q:x".to_owned(), LexemeKind::SavedQuery),
+    ]);
+}
+
+#[test]
+fn test_lex_spread_values() {
+    use crate::cli::query::args::Args;
+
+    let args = Args::new(&["meta", "get", "where", "title", "=", "and", "or", "name", "like", "(draft)", "or", "a", "is", "not", "b)", "or", "c", "=", "$d"]);
+    let query = (3..19).map(|i| args[i].clone()).into_vec();
+
+    let mut lexemes = match lex(&query, args.cmdline()) {
+        Ok(l) => l,
+        Err(_) => panic!("Failed to lex {}", args.cmdline())
+    };
+
+    let mut kinds = Vec::new();
+    while let Some(l) = lexemes.pop() {
+        kinds.push((l.token().to_owned(), l.kind()));
+    }
+
+    assert_eq!(kinds, vec![
+        ("title".to_owned(), LexemeKind::Key),
+        ("=".to_owned(), LexemeKind::Equals(EqualityKind::Strict)),
+        ("and".to_owned(), LexemeKind::Argument),
+        ("or".to_owned(), LexemeKind::Or),
+        ("name".to_owned(), LexemeKind::Key),
+        ("like".to_owned(), LexemeKind::Equals(EqualityKind::Like)),
+        ("(draft)".to_owned(), LexemeKind::Argument),
+        ("or".to_owned(), LexemeKind::Or),
+        ("a".to_owned(), LexemeKind::Key),
+        ("is".to_owned(), LexemeKind::Equals(EqualityKind::Strict)),
+        ("not".to_owned(), LexemeKind::Not),
+        ("b)".to_owned(), LexemeKind::Argument),
+        ("or".to_owned(), LexemeKind::Or),
+        ("c".to_owned(), LexemeKind::Key),
+        ("=".to_owned(), LexemeKind::Equals(EqualityKind::Strict)),
+        ("$d".to_owned(), LexemeKind::KeyRef),
+    ]);
+}
@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 
-use crate::cli::query::args::ArgsIter;
+use crate::cli::query::args::{ArgsIter, Span};
 use crate::linq::collectors::IntoVec;
 use std::borrow::Borrow;
 
@@ -9,12 +9,12 @@ pub struct OwnedLexeme {
     pub token: String,
     pub kind: LexemeKind,
     pub cmdline: String,
-    pub cmdline_index: usize
+    pub span: Span
 }
 
 impl<'a> Borrow<Lexeme<'a, 'a>> for OwnedLexeme {
     fn borrow(&'a self) -> &Lexeme<'a, 'a> {
-        &Lexeme{token: &self.token, kind: self.kind, cmdline_ptr: &self.cmdline, cmdline_index: self.cmdline_index}
+        &Lexeme{token: &self.token, kind: self.kind, cmdline_ptr: &self.cmdline, span: self.span}
     }
 }
 
@@ -23,12 +23,12 @@ pub struct Lexeme<'a, 'b> {
     token: &'a str,
     kind: LexemeKind,
     cmdline_ptr: &'b str,
-    cmdline_index: usize
+    span: Span
 }
 
 impl<'a, 'b> Lexeme<'a, 'b> {
-    pub fn new(token: &'a str, kind: LexemeKind, cmdline_ptr: &'b str, cmdline_index: usize) -> Self {
-        Lexeme { token, kind, cmdline_ptr, cmdline_index }
+    pub fn new(token: &'a str, kind: LexemeKind, cmdline_ptr: &'b str, span: Span) -> Self {
+        Lexeme { token, kind, cmdline_ptr, span }
     }
 
     pub fn token(&self) -> &str {
@@ -43,12 +43,17 @@ impl<'a, 'b> Lexeme<'a, 'b> {
         self.cmdline_ptr
     }
 
+    /// Where the token is in the command line.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
     pub fn to_owned(&self) -> OwnedLexeme {
         OwnedLexeme {
             token: self.token.to_owned(),
             kind: self.kind,
             cmdline: self.cmdline_ptr.to_owned(),
-            cmdline_index: self.cmdline_index
+            span: self.span
         }
     }
 }
@@ -60,16 +65,71 @@ pub enum LexemeKind {
     Equals(EqualityKind),
     Or,
     And,
+    Not,
     Key,
+    KeyRef,
+    SavedQuery,
     Value,
+    /// A whole command-line argument taken as a value as-is, spaces and all.
+    Argument,
     In,
+    Under,
+    Directly,
+    Any,
+    All,
+    Near,
+    Within,
+    Parent,
+    Ancestor,
+    Has,
+    Dot,
+    Order,
+    By,
+    Asc,
+    Desc,
+    Limit,
+    Offset,
     Comma,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum EqualityKind {
     Strict,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
     Matches,
+    MatchesIgnoreCase,
+    Like,
+    Glob,
+}
+
+impl EqualityKind {
+    /// The operator as it is written in a query.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            EqualityKind::Strict => "=",
+            EqualityKind::NotEqual => "!=",
+            EqualityKind::Less => "<",
+            EqualityKind::LessEqual => "<=",
+            EqualityKind::Greater => ">",
+            EqualityKind::GreaterEqual => ">=",
+            EqualityKind::Matches => "matches",
+            EqualityKind::MatchesIgnoreCase => "imatches",
+            EqualityKind::Like => "like",
+            EqualityKind::Glob => "glob",
+        }
+    }
+
+    /// Returns true if the operator can compare the values of two keys, which the pattern operators can't.
+    pub fn compares_keys(&self) -> bool {
+        match self {
+            EqualityKind::Strict | EqualityKind::NotEqual | EqualityKind::Less | EqualityKind::LessEqual | EqualityKind::Greater | EqualityKind::GreaterEqual => true,
+            EqualityKind::Matches | EqualityKind::MatchesIgnoreCase | EqualityKind::Like | EqualityKind::Glob => false
+        }
+    }
 }
 
 pub struct LexemeQueue<'a, 'b> {
@@ -2,4 +2,7 @@ pub mod lex;
 pub mod lexeme;
 pub mod parse;
 pub mod args;
+pub mod builtin;
+pub mod explain;
+pub mod diagnostic;
 pub mod selector;
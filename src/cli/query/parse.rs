@@ -1,14 +1,48 @@
 /// Parses a query from command-line arguments.
 /// The grammar for a query is as follows:
 ///
+/// query -> or-query order-by? limit? offset?
+/// order-by -> order by order-keys
+/// order-keys -> order-key , order-keys | order-key
+/// order-key -> key | key asc | key desc
+///     (values that look like numbers sort numerically and before the rest, entries without the key sort last, and ties are broken by path)
+/// limit -> limit [0-9]+
+/// offset -> offset [0-9]+
 /// or-query -> and-query or or-query | and-query
 /// and-query -> factor and and-query | factor
-/// factor -> ( or-query ) | key | key equals value | key in ( values ) // (command-line arguments in quotes e.g. 'this and that' are treated as being in parentheses)
-/// key -> [a-zA-Z0-9\-_]+
-/// equals -> = | == | is | matches
+///     (&&, || and ! can be written for and, or and not, and every keyword ignores case, so AND and Order By work too)
+/// factor -> not factor | ( or-query ) | saved-query | key-factor | quantifier key-pattern-factor | scope . key-factor | scope has key | under value | directly in value // (command-line arguments in quotes e.g. 'this and that' are treated as being in parentheses, unless the whole query is one argument)
+///     (under matches everything below the given directory, directly in matches only the entries the directory contains itself)
+/// key-factor -> key | key equals value | key equals key-reference | key in ( values ) | key near value | key near value within [0-9]+
+///     (near matches values at most that many single-character insertions, deletions or substitutions away, by default a quarter of the value's length but at least one.
+///      a query with a 'near' and no 'order by' is ranked by how near the entries' values are for the first 'near' outside a 'not')
+/// key-reference -> $key
+///     (the value the same entry has for another key, e.g. end_date > $start_date. only =, !=, <, <=, > and >= can compare two keys)
+/// quantifier -> any | all
+///     (any matches if at least one of the entry's keys matching the pattern satisfies the rest of the factor,
+///      all matches if the entry has at least one key matching the pattern and every one of them satisfies it)
+/// key-pattern-factor -> key-factor with a key-pattern in place of the key
+/// scope -> parent | ancestor
+///     (parent applies the key-factor to the directory the entry is in, ancestor matches if any directory above the entry satisfies it.
+///      the directory's metadata is used on its own, so parent.status = archived doesn't care about the entry's own status. scope has key is scope.key)
+/// key-pattern -> [a-zA-Z0-9\-_*?]+ | quotation
+///     (* matches any run of characters and ? matches one character, so review-* matches review-alice and review-bob)
+/// saved-query -> @q:[a-zA-Z0-9\-_]+
+///     (the query saved under that name with 'meta query save', as if it were written in parentheses)
+/// key -> [a-zA-Z0-9\-_]+ | builtin-key | quotation
+///     (a key spelled like a keyword, such as order, limit or parent, has to be quoted to be used as a key, e.g. "order" = 5)
+/// builtin-key -> @name | @path | @ext | @size | @mtime | @type | @hash
+///     (attributes of the entry itself: @size is in bytes, @mtime is in seconds since the Unix epoch, @type is 'file' or 'dir', @hash is lowercase hex)
+/// equals -> = | == | is | != | is not | < | <= | > | >= | matches | imatches | like | glob
+///     (values that look like numbers are compared numerically and are less than the ones that don't, matches/imatches take a regex that may match anywhere in the value,
+///      like/glob take a shell pattern where * matches any run of characters, ? matches one character, and \ escapes the next character.
+///      like ignores ASCII case, glob doesn't and also accepts [...] character classes)
 /// values -> value values | value , values | value
-/// value -> key | quotation
-/// quotation -> [[json quote]]
+/// value -> key | [a-zA-Z0-9\-_][a-zA-Z0-9\-_.]* | quotation
+///     (when the query is spread over several arguments, an argument after an operator is taken whole as the value, even if it has spaces or parentheses
+///      or is spelled like a keyword, since the shell already removed its quotes. e.g. meta get where title = "hello (world)" matches the title 'hello (world)')
+/// quotation -> "..." | '...'
+///     (either quote can be escaped inside, along with \n \t \r \0 \b \f \/ \\, \xNN for a byte of UTF-8, and \uNNNN or \u{N...} for a Unicode code point)
 
 use crate::linq::collectors::IntoVec;
 use std::convert::TryFrom;
@@ -16,24 +50,271 @@ use crate::format::str::{ToCharIterator, StrExtensions};
 use crate::cli::query::parse::LexError::NonLexableSequence;
 use std::mem::take;
 use crate::cli::query::parse::Factor::KeyEqualsValue;
-use crate::cli::query::lex::{LexError, lex};
+use crate::cli::query::lex::{LexError, lex, unquote};
 use crate::cli::query::lexeme::{LexemeQueue, LexemeKind, Lexeme, EqualityKind, OwnedLexeme};
+use crate::cli::query::builtin::{is_builtin, BuiltinKey, BUILTIN_KEYS};
+use crate::cli::lang;
+use crate::database::path::Path;
+use std::fmt;
+use std::collections::HashSet;
 
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Query {
+    pub filter: OrQuery,
+    pub order_by: Vec<OrderKey>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// Whether entries also have the metadata of the directories above them. This comes from --inherited rather than the query's text, so it isn't printed.
+    pub inherited: bool
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct OrderKey {
+    pub key: String,
+    pub descending: bool
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct OrQuery {
-    and_query: AndQuery,
-    next: Option<Box<OrQuery>>
+    pub and_query: AndQuery,
+    pub next: Option<Box<OrQuery>>
 }
 
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct AndQuery {
-    factor: Factor,
-    next: Option<Box<AndQuery>>
+    pub factor: Factor,
+    pub next: Option<Box<AndQuery>>
 }
 
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Factor {
     Query(Box<OrQuery>),
+    Not(Box<Factor>),
     Key(String),
     KeyEqualsValue((String, EqualityKind, String)),
-    KeyIn((String, Vec<String>))
+    /// Compares the values an entry has for two of its keys.
+    KeyEqualsKey((String, EqualityKind, String)),
+    KeyIn((String, Vec<String>)),
+    /// Matches values within some number of edits of a value, or the default number for it if None.
+    KeyNear((String, String, Option<usize>)),
+    Under(String),
+    DirectlyIn(String),
+    SavedQuery(String),
+    /// A key factor whose key is a pattern, applied to any or all of the keys matching it.
+    Quantified((Quantifier, Box<Factor>)),
+    /// A key factor applied to the directory an entry is in, or to any of the directories above it, instead of the entry itself.
+    Scoped((Scope, Box<Factor>))
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Quantifier {
+    Any,
+    All
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Scope {
+    Parent,
+    Ancestor
+}
+
+/// Returns true if a key has wildcards in it, making it a pattern for 'any' or 'all'.
+pub fn is_key_pattern(key: &str) -> bool {
+    key.contains('*') || key.contains('?')
+}
+
+/// Returns true if a key matches a key pattern, where '*' matches any run of characters and '?' matches exactly one, the same as in a query.
+pub fn key_matches(pattern: &str, key: &str) -> bool {
+    let p = pattern.chars().into_vec();
+    let k = key.chars().into_vec();
+    let (mut pi, mut ki) = (0, 0);
+    // where the last '*' was, and where in the key it started matching from
+    let mut star = None;
+
+    while ki < k.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == k[ki]) {
+            pi += 1;
+            ki += 1;
+        }
+        else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ki));
+            pi += 1;
+        }
+        else if let Some((sp, sk)) = star {
+            // let the '*' swallow one more character and try again
+            star = Some((sp, sk + 1));
+            pi = sp + 1;
+            ki = sk + 1;
+        }
+        else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Quotes a value so that lexing it gives back the same string.
+pub fn quote(s: &str) -> String {
+    serde_json::to_string(s).expect("Serializing a string to JSON should never fail.")
+}
+
+/// Returns a key the way it has to be written in a query: bare, or quoted if it would be lexed as something else, like a keyword.
+pub fn quote_key(k: &str) -> String {
+    let args = [(k.to_owned(), 0)];
+
+    match lex(&args, k) {
+        Ok(l) if l.len() == 1 && l.peek().map(|x| x.kind()) == Some(LexemeKind::Key) => k.to_owned(),
+        _ => quote(k)
+    }
+}
+
+impl Query {
+    /// Returns a query matching every file and directory, since every entry has a type.
+    pub fn everything(inherited: bool) -> Query {
+        Query {
+            filter: OrQuery { and_query: AndQuery { factor: Factor::Key(BuiltinKey::Type.key().to_owned()), next: None }, next: None },
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+            inherited,
+        }
+    }
+
+    /// Returns the metadata keys the query mentions, each once and in the order they first appear.
+    ///
+    /// Built-in keys, key patterns and the keys inside saved queries are left out.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut ret = Vec::new();
+        or_query_keys(&self.filter, &mut ret);
+
+        for k in &self.order_by {
+            ret.push(k.key.as_str());
+        }
+
+        let mut seen = HashSet::new();
+        ret.into_iter()
+            .filter(|k| !is_builtin(k) && !is_key_pattern(k) && seen.insert(*k))
+            .collect()
+    }
+}
+
+fn or_query_keys<'a>(query: &'a OrQuery, out: &mut Vec<&'a str>) {
+    and_query_keys(&query.and_query, out);
+
+    if let Some(q) = &query.next {
+        or_query_keys(q, out);
+    }
+}
+
+fn and_query_keys<'a>(query: &'a AndQuery, out: &mut Vec<&'a str>) {
+    factor_keys(&query.factor, out);
+
+    if let Some(q) = &query.next {
+        and_query_keys(q, out);
+    }
+}
+
+fn factor_keys<'a>(factor: &'a Factor, out: &mut Vec<&'a str>) {
+    match factor {
+        Factor::Query(q) => or_query_keys(q, out),
+        Factor::Not(f) | Factor::Quantified((_, f)) | Factor::Scoped((_, f)) => factor_keys(f, out),
+        Factor::Key(k) | Factor::KeyEqualsValue((k, _, _)) | Factor::KeyIn((k, _)) | Factor::KeyNear((k, _, _)) => out.push(k),
+        Factor::KeyEqualsKey((k, _, other)) => {
+            out.push(k);
+            out.push(other);
+        }
+        Factor::Under(_) | Factor::DirectlyIn(_) | Factor::SavedQuery(_) => {}
+    }
+}
+
+// The Display implementations print a query in a canonical form, which parses back into an identical query.
+// Keys are printed bare, values are always double-quoted, and 'is'/'=='/'is not' are printed as '='/'!='.
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.filter)?;
+
+        if !self.order_by.is_empty() {
+            write!(f, " order by {}", self.order_by.iter().map(|k| k.to_string()).into_vec().join(", "))?;
+        }
+
+        if let Some(n) = self.limit {
+            write!(f, " limit {}", n)?;
+        }
+
+        if let Some(n) = self.offset {
+            write!(f, " offset {}", n)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for OrderKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", quote_key(&self.key), if self.descending { " desc" } else { "" })
+    }
+}
+
+impl fmt::Display for OrQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.and_query)?;
+
+        match &self.next {
+            Some(s) => write!(f, " or {}", s),
+            None => Ok(())
+        }
+    }
+}
+
+impl fmt::Display for AndQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.factor)?;
+
+        match &self.next {
+            Some(s) => write!(f, " and {}", s),
+            None => Ok(())
+        }
+    }
+}
+
+impl fmt::Display for Factor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Factor::Query(q) => write!(f, "({})", q),
+            Factor::Not(x) => write!(f, "not {}", x),
+            Factor::Key(k) => write!(f, "{}", quote_key(k)),
+            Factor::KeyEqualsValue((k, e, v)) => write!(f, "{} {} {}", quote_key(k), e.symbol(), quote(v)),
+            Factor::KeyEqualsKey((k, e, other)) => write!(f, "{} {} ${}", quote_key(k), e.symbol(), other),
+            Factor::KeyIn((k, values)) => write!(f, "{} in ({})", quote_key(k), values.iter().map(|v| quote(v)).into_vec().join(", ")),
+            Factor::KeyNear((k, v, None)) => write!(f, "{} near {}", quote_key(k), quote(v)),
+            Factor::KeyNear((k, v, Some(n))) => write!(f, "{} near {} within {}", quote_key(k), quote(v), n),
+            Factor::Under(p) => write!(f, "under {}", quote(p)),
+            Factor::DirectlyIn(p) => write!(f, "directly in {}", quote(p)),
+            Factor::SavedQuery(name) => write!(f, "@q:{}", name),
+            Factor::Quantified((q, x)) => write!(f, "{} {}", q, x),
+            Factor::Scoped((s, x)) => write!(f, "{}.{}", s, x),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Parent => write!(f, "parent"),
+            Scope::Ancestor => write!(f, "ancestor")
+        }
+    }
+}
+
+impl fmt::Display for Quantifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quantifier::Any => write!(f, "any"),
+            Quantifier::All => write!(f, "all")
+        }
+    }
 }
 
 pub enum ParseError {
@@ -42,7 +323,44 @@ pub enum ParseError {
     TrailingToken(OwnedLexeme)
 }
 
-pub fn parse(lexemes: &mut LexemeQueue) -> Result<OrQuery, ParseError> {
+pub fn parse(lexemes: &mut LexemeQueue) -> Result<Query, ParseError> {
+    let filter = parse_or_query(lexemes)?;
+
+    let order_by = match lexemes.pop_kind(LexemeKind::Order) {
+        Some(s) => {
+            match lexemes.pop() {
+                Some(t) => {
+                    if t.kind() != LexemeKind::By {
+                        return Err(ParseError::UnexpectedToken((t.to_owned(), "Expected 'by' after 'order'.".to_owned())))
+                    }
+                }
+                None => return Err(ParseError::UnexpectedEOF(format!("Expected 'by' after '{}'.", s.token())))
+            }
+
+            parse_order_keys(lexemes)?
+        }
+        None => Vec::new()
+    };
+
+    let limit = match lexemes.pop_kind(LexemeKind::Limit) {
+        Some(s) => Some(parse_count(lexemes, &s)?),
+        None => None
+    };
+
+    let offset = match lexemes.pop_kind(LexemeKind::Offset) {
+        Some(s) => Some(parse_count(lexemes, &s)?),
+        None => None
+    };
+
+    if let Some(s) = lexemes.peek() {
+        return Err(ParseError::TrailingToken(s.to_owned()))
+    }
+
+    Ok(Query { filter, order_by, limit, offset, inherited: false })
+}
+
+/// Parses a query with no 'order by', 'limit' or 'offset', like the ones that can be saved.
+pub fn parse_filter(lexemes: &mut LexemeQueue) -> Result<OrQuery, ParseError> {
     let or_query = parse_or_query(lexemes)?;
 
     if let Some(s) = lexemes.peek() {
@@ -52,6 +370,37 @@ pub fn parse(lexemes: &mut LexemeQueue) -> Result<OrQuery, ParseError> {
     Ok(or_query)
 }
 
+fn parse_text_with<T, F: FnOnce(&mut LexemeQueue) -> Result<T, ParseError>>(text: &str, f: F) -> Result<T, String> {
+    let args = [(text.to_owned(), 0)];
+
+    let mut lexemes = match lex(&args, text) {
+        Ok(l) => l,
+        Err(LexError::StringError(_, span)) => return Err(format!("The quoted value at character {} is not closed or contains an invalid escape sequence.", span.index + 1)),
+        Err(LexError::UnknownToken(a)) => return Err(format!("The token '{}' at character {} was unrecognized.", a.arg, a.position + 1))
+    };
+
+    match f(&mut lexemes) {
+        Ok(q) => Ok(q),
+        Err(ParseError::UnexpectedEOF(s)) => Err(s),
+        Err(ParseError::UnexpectedToken((l, s))) => Err(format!("Unexpected '{}' at character {}. {}", l.token, l.span.index + 1, s)),
+        Err(ParseError::TrailingToken(l)) => Err(format!("Unexpected '{}' at character {} after the end of the query.", l.token, l.span.index + 1))
+    }
+}
+
+/// Lexes and parses a query with no 'order by', 'limit' or 'offset' held in a single string, such as one stored in the database.
+///
+/// On failure, returns a description of what's wrong with the query.
+pub fn parse_text(text: &str) -> Result<OrQuery, String> {
+    parse_text_with(text, parse_filter)
+}
+
+/// Lexes and parses a whole query held in a single string.
+///
+/// On failure, returns a description of what's wrong with the query.
+pub fn parse_query_text(text: &str) -> Result<Query, String> {
+    parse_text_with(text, parse)
+}
+
 pub fn parse_or_query(lexemes: &mut LexemeQueue) -> Result<OrQuery, ParseError> {
     let and_query = parse_and_query(lexemes)?;
 
@@ -89,6 +438,21 @@ pub fn parse_factor(lexemes: &mut LexemeQueue) -> Result<Factor, ParseError> {
     };
 
     match tok.kind() {
+        LexemeKind::Not => Ok(Factor::Not(Box::new(parse_factor(lexemes)?))),
+        LexemeKind::Under => Ok(Factor::Under(parse_path(lexemes, &tok)?)),
+        LexemeKind::SavedQuery => Ok(Factor::SavedQuery(tok.token().trim_start_matches("@q:").to_owned())),
+        LexemeKind::Directly => {
+            match lexemes.pop() {
+                Some(s) => {
+                    if s.kind() != LexemeKind::In {
+                        return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected 'in' after 'directly'.".to_owned())))
+                    }
+                }
+                None => return Err(ParseError::UnexpectedEOF("Expected 'in' after 'directly'.".to_owned()))
+            }
+
+            Ok(Factor::DirectlyIn(parse_path(lexemes, &tok)?))
+        }
         LexemeKind::LParen => {
             let expr = parse_or_query(lexemes)?;
 
@@ -96,73 +460,434 @@ pub fn parse_factor(lexemes: &mut LexemeQueue) -> Result<Factor, ParseError> {
             match rparen {
                 Some(s) => {
                     if s.kind() != LexemeKind::RParen {
-                        return Err(ParseError::UnexpectedEOF(("Expected a ')'. Most likely you forgot to include a closing ')'".to_owned())))
+                        return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected a ')'. Most likely you forgot to include a closing ')'".to_owned())))
                     }
                     Ok(Factor::Query(Box::new(expr)))
                 }
-                None => Err(ParseError::UnexpectedToken((tok.to_owned(), "Expected a ')'. Most likely you forgot to include a closing ')'".to_owned())))
+                None => Err(ParseError::UnexpectedEOF("Expected a ')'. Most likely you forgot to include a closing ')'".to_owned()))
             }
         },
-        LexemeKind::Key => {
-            let next = match lexemes.pop() {
-                Some(s) => s,
-                None => return Ok(Factor::Key(tok.token().to_owned()))
+        LexemeKind::Any | LexemeKind::All => {
+            let quantifier = if tok.kind() == LexemeKind::Any { Quantifier::Any } else { Quantifier::All };
+
+            let (key, name) = match lexemes.pop() {
+                Some(s) => match key_of(&s)? {
+                    Some(k) => (s, k),
+                    None => return Err(ParseError::UnexpectedToken((s.to_owned(), format!("Expected a key pattern like 'review-*' after '{}'.", tok.token()))))
+                }
+                None => return Err(ParseError::UnexpectedEOF(format!("Expected a key pattern like 'review-*' after '{}'.", tok.token())))
             };
 
-            match next.kind() {
-                LexemeKind::In => {
-                    let lparen = lexemes.pop();
-                    match lparen {
-                        Some(s) => {
-                            if s.kind() != LexemeKind::LParen {
-                                return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected '(' after 'in'".to_owned())))
-                            }
-                        }
-                        None => return Err(ParseError::UnexpectedEOF("Expected ')' after 'in'. Most likely you have an extra trailing 'in'.".to_owned()))
+            if is_builtin(&name) {
+                return Err(ParseError::UnexpectedToken((key.to_owned(), format!("'{}' can only be used with metadata keys, not built-in ones.", tok.token()))))
+            }
+
+            let factor = parse_key_factor(&name, lexemes)?;
+
+            if let Factor::KeyEqualsKey(_) = factor {
+                return Err(ParseError::UnexpectedToken((key.to_owned(), format!("'{}' can't compare a key pattern with another key.", tok.token()))))
+            }
+
+            Ok(Factor::Quantified((quantifier, Box::new(factor))))
+        }
+        LexemeKind::Parent | LexemeKind::Ancestor => {
+            let scope = if tok.kind() == LexemeKind::Parent { Scope::Parent } else { Scope::Ancestor };
+            let expected = format!("Expected '.' or 'has' and then a key after '{0}', e.g. '{0}.status = archived' or '{0} has status'.", tok.token());
+
+            let has = match lexemes.pop() {
+                Some(s) if s.kind() == LexemeKind::Dot => false,
+                Some(s) if s.kind() == LexemeKind::Has => true,
+                Some(s) => return Err(ParseError::UnexpectedToken((s.to_owned(), expected))),
+                None => return Err(ParseError::UnexpectedEOF(expected))
+            };
+
+            let (key, name) = match lexemes.pop() {
+                Some(s) => match key_of(&s)? {
+                    Some(k) => (s, k),
+                    None => return Err(ParseError::UnexpectedToken((s.to_owned(), expected)))
+                }
+                None => return Err(ParseError::UnexpectedEOF(expected))
+            };
+
+            check_key(&key, &name)?;
+
+            let factor = if has { Factor::Key(name) } else { parse_key_factor(&name, lexemes)? };
+
+            Ok(Factor::Scoped((scope, Box::new(factor))))
+        }
+        LexemeKind::Key | LexemeKind::Value => {
+            let name = key_of(&tok)?.expect("A key or a quoted string always names a key.");
+            check_key(&tok, &name)?;
+            parse_key_factor(&name, lexemes)
+        }
+        _ => Err(ParseError::UnexpectedToken((tok.to_owned(), "Expected 'not', 'under', 'directly', 'any', 'all', 'parent', 'ancestor', '(', a saved query or a key.".to_owned())))
+    }
+}
+
+/// Returns the key a lexeme names, or None if it can't name one.
+///
+/// A key can be quoted, so that keys spelled like keywords can still be used, but it still has to be made of the characters a bare key is.
+fn key_of(tok: &Lexeme) -> Result<Option<String>, ParseError> {
+    match tok.kind() {
+        LexemeKind::Key => Ok(Some(tok.token().to_owned())),
+        LexemeKind::Value => {
+            let key = unquote(tok.token());
+            let name = key.strip_prefix('@').unwrap_or(&key);
+
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || "-_*?".contains(c)) {
+                return Err(ParseError::UnexpectedToken((tok.to_owned(), "A quoted key can only contain letters, numbers, '-' and '_', along with '*' and '?' in a key pattern.".to_owned())))
+            }
+
+            Ok(Some(key))
+        }
+        _ => Ok(None)
+    }
+}
+
+/// Checks that the key of a key factor is a key an entry could have: a known built-in key, or a metadata key that isn't a pattern.
+fn check_key(tok: &Lexeme, key: &str) -> Result<(), ParseError> {
+    if is_builtin(key) && BuiltinKey::from_key(key).is_none() {
+        return Err(ParseError::UnexpectedToken((tok.to_owned(), format!("Unknown built-in key. Expected {}.", lang::or(BUILTIN_KEYS.iter().map(|x| x.0))))))
+    }
+
+    if is_key_pattern(key) {
+        return Err(ParseError::UnexpectedToken((tok.to_owned(), format!("A key pattern needs 'any' or 'all' in front of it, e.g. 'any {} = value'.", tok.token()))))
+    }
+
+    if key.contains('.') {
+        return Err(ParseError::UnexpectedToken((tok.to_owned(), "Keys can't contain '.'. To use the metadata of the directories above an entry, write 'parent.key' or 'ancestor.key'.".to_owned())))
+    }
+
+    Ok(())
+}
+
+/// Parses what follows the key of a key factor: nothing, a comparison with a value, or 'in' and a list of values.
+fn parse_key_factor(key: &str, lexemes: &mut LexemeQueue) -> Result<Factor, ParseError> {
+    let next = match lexemes.pop_predicate(|l| l.kind() == LexemeKind::In || l.kind() == LexemeKind::Near || matches!(l.kind(), LexemeKind::Equals(_))) {
+        Some(s) => s,
+        None => return Ok(Factor::Key(key.to_owned()))
+    };
+
+    match next.kind() {
+        LexemeKind::In => {
+            let lparen = lexemes.pop();
+            match lparen {
+                Some(s) => {
+                    if s.kind() != LexemeKind::LParen {
+                        return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected '(' after 'in'".to_owned())))
                     }
+                }
+                None => return Err(ParseError::UnexpectedEOF("Expected ')' after 'in'. Most likely you have an extra trailing 'in'.".to_owned()))
+            }
 
-                    let values = parse_values(lexemes)?;
+            let values = parse_values(lexemes);
 
-                    lexemes.pop_kind(LexemeKind::Comma);
+            lexemes.pop_kind(LexemeKind::Comma);
 
-                    let rparen = lexemes.pop();
-                    match rparen {
-                        Some(s) => {
-                            if s.kind() != LexemeKind::RParen {
-                                return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected ')' to close the 'in' values. Most likely you forgot to include the closing ')'.".to_owned())))
-                            }
-                        }
-                        None => return Err(ParseError::UnexpectedEOF("Expected ')' to close the 'in' values. Most likely you forgot to include the closing ')'.".to_owned()))
+            let rparen = lexemes.pop();
+            match rparen {
+                Some(s) => {
+                    if s.kind() != LexemeKind::RParen {
+                        return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected ')' to close the 'in' values. Most likely you forgot to include the closing ')'.".to_owned())))
                     }
+                }
+                None => return Err(ParseError::UnexpectedEOF("Expected ')' to close the 'in' values. Most likely you forgot to include the closing ')'.".to_owned()))
+            }
 
-                    Ok(Factor::KeyIn((tok.token().to_owned(), values)))
+            Ok(Factor::KeyIn((key.to_owned(), values)))
+        }
+        LexemeKind::Near => {
+            let val = match lexemes.pop() {
+                Some(s) => {
+                    if !is_value(&s) {
+                        return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected a value after 'near'.".to_owned())))
+                    }
+                    s
                 }
-                LexemeKind::Equals(e) => {
-                    let val = lexemes.pop();
-                    match val {
-                        Some(s) => {
-                            if s.kind() != LexemeKind::Key && s.kind() != LexemeKind::Value {
-                                return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected a key or a value.".to_owned())))
-                            }
-                        }
-                        None => return Err(ParseError::UnexpectedEOF(format!("Expected a value after '{}'.", next.token())))
+                None => return Err(ParseError::UnexpectedEOF("Expected a value after 'near'.".to_owned()))
+            };
+
+            let within = match lexemes.pop_kind(LexemeKind::Within) {
+                Some(s) => Some(parse_count(lexemes, &s)?),
+                None => None
+            };
+
+            Ok(Factor::KeyNear((key.to_owned(), value_of(&val), within)))
+        }
+        LexemeKind::Equals(e) => {
+            // 'is not' is lexed as 'is' followed by 'not'
+            let e = if e == EqualityKind::Strict && next.token().eq_ignore_ascii_case("is") && lexemes.pop_kind(LexemeKind::Not).is_some() {
+                EqualityKind::NotEqual
+            } else {
+                e
+            };
+
+            let val = match lexemes.pop() {
+                Some(s) => {
+                    if !is_value(&s) && s.kind() != LexemeKind::KeyRef {
+                        return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected a key or a value.".to_owned())))
                     }
-                    Ok(Factor::KeyEqualsValue((tok.token().to_owned(), e, val.token().to_owned())))
-                },
-                _ => Err(ParseError::UnexpectedToken((next.to_owned(), "Expected 'in', '=', '==', or 'matches'.".to_owned())))
+                    s
+                }
+                None => return Err(ParseError::UnexpectedEOF(format!("Expected a value after '{}'.", next.token())))
+            };
+
+            if val.kind() == LexemeKind::KeyRef {
+                let other = val.token().trim_start_matches('$');
+
+                if !e.compares_keys() {
+                    return Err(ParseError::UnexpectedToken((next.to_owned(), format!("Only =, !=, <, <=, > and >= can compare two keys, so '{}' can't be used with ${}.", next.token(), other))))
+                }
+
+                if is_builtin(other) && BuiltinKey::from_key(other).is_none() {
+                    return Err(ParseError::UnexpectedToken((val.to_owned(), format!("Unknown built-in key. Expected {}.", lang::or(BUILTIN_KEYS.iter().map(|x| x.0))))))
+                }
+
+                return Ok(Factor::KeyEqualsKey((key.to_owned(), e, other.to_owned())))
+            }
+
+            Ok(Factor::KeyEqualsValue((key.to_owned(), e, value_of(&val))))
+        },
+        _ => Err(ParseError::UnexpectedToken((next.to_owned(), "Expected 'in', 'near', '=', '==', '!=', '<', '<=', '>', '>=', 'matches', 'imatches', 'like', or 'glob'.".to_owned())))
+    }
+}
+
+/// Parses the directory path following `keyword`.
+pub fn parse_path(lexemes: &mut LexemeQueue, keyword: &Lexeme) -> Result<String, ParseError> {
+    match lexemes.pop() {
+        Some(s) => {
+            if !is_value(&s) {
+                return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected a directory path. Paths containing '/' need to be quoted.".to_owned())))
+            }
+            Ok(Path::new(&value_of(&s)).str().to_owned())
+        }
+        None => Err(ParseError::UnexpectedEOF(format!("Expected a directory path after '{}'.", keyword.token())))
+    }
+}
+
+/// Parses the keys following 'order by'.
+pub fn parse_order_keys(lexemes: &mut LexemeQueue) -> Result<Vec<OrderKey>, ParseError> {
+    let mut ret = Vec::new();
+
+    loop {
+        let key = match lexemes.pop() {
+            Some(s) => {
+                let key = match key_of(&s)? {
+                    Some(k) => k,
+                    None => return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected a key to order by.".to_owned())))
+                };
+                if is_builtin(&key) && BuiltinKey::from_key(&key).is_none() {
+                    return Err(ParseError::UnexpectedToken((s.to_owned(), format!("Unknown built-in key. Expected {}.", lang::or(BUILTIN_KEYS.iter().map(|x| x.0))))))
+                }
+                if is_key_pattern(&key) {
+                    return Err(ParseError::UnexpectedToken((s.to_owned(), "Expected a key to order by, not a key pattern.".to_owned())))
+                }
+                key
             }
+            None => return Err(ParseError::UnexpectedEOF("Expected a key to order by.".to_owned()))
+        };
+
+        let descending = match lexemes.pop_predicate(|l| l.kind() == LexemeKind::Asc || l.kind() == LexemeKind::Desc) {
+            Some(s) => s.kind() == LexemeKind::Desc,
+            None => false
+        };
+
+        ret.push(OrderKey { key, descending });
+
+        if lexemes.pop_kind(LexemeKind::Comma).is_none() {
+            return Ok(ret);
         }
-        _ => Err(ParseError::UnexpectedToken((tok.to_owned(), "Expected '(' or a key.".to_owned())))
+    }
+}
+
+/// Parses the number following `keyword`.
+pub fn parse_count(lexemes: &mut LexemeQueue, keyword: &Lexeme) -> Result<usize, ParseError> {
+    match lexemes.pop() {
+        Some(s) => match s.token().parse::<usize>() {
+            Ok(n) if s.kind() == LexemeKind::Key => Ok(n),
+            _ => Err(ParseError::UnexpectedToken((s.to_owned(), format!("Expected a whole number after '{}'.", keyword.token()))))
+        },
+        None => Err(ParseError::UnexpectedEOF(format!("Expected a whole number after '{}'.", keyword.token())))
     }
 }
 
 pub fn parse_values(lexemes: &mut LexemeQueue) -> Vec<String> {
     let mut ret = Vec::new();
 
-    while let Some(s) = lexemes.pop_predicate(|l| l.kind() == LexemeKind::Key || l.kind() == LexemeKind::Value) {
-        ret.add(s.token().to_owned());
+    while let Some(s) = lexemes.pop_predicate(|l| is_value(l)) {
+        ret.push(value_of(&s));
         lexemes.pop_kind(LexemeKind::Comma);
     }
 
     ret
 }
+
+/// Returns true if a lexeme can be used as a value.
+fn is_value(l: &Lexeme) -> bool {
+    matches!(l.kind(), LexemeKind::Key | LexemeKind::Value | LexemeKind::Argument)
+}
+
+/// Returns the value a lexeme stands for, without any quotes or escape sequences.
+fn value_of(l: &Lexeme) -> String {
+    match l.kind() {
+        LexemeKind::Argument => l.token().to_owned(),
+        _ => unquote(l.token())
+    }
+}
+
+#[cfg(test)]
+fn parse_str(s: &str) -> OrQuery {
+    match parse_text(s) {
+        Ok(q) => q,
+        Err(e) => panic!("Failed to parse {}: {}", s, e)
+    }
+}
+
+#[test]
+fn test_print_round_trip() {
+    let cases = [
+        ("a", "a"),
+        ("@size", "@size"),
+        ("not a", "not a"),
+        ("not not a", "not not a"),
+        ("a and b or c", "a and b or c"),
+        ("a or b and c", "a or b and c"),
+        ("a and (b or c)", "a and (b or c)"),
+        ("not (a or b) and c", "not (a or b) and c"),
+        ("((a))", "((a))"),
+        ("a = b", r#"a = "b""#),
+        ("a == 'b'", r#"a = "b""#),
+        ("a is b", r#"a = "b""#),
+        ("a is not b", r#"a != "b""#),
+        ("a != \"b\"", r#"a != "b""#),
+        ("a < 1 and a <= 2 or a > 3 and a >= 4", r#"a < "1" and a <= "2" or a > "3" and a >= "4""#),
+        ("a matches '^x+$' or a imatches 'X'", r#"a matches "^x+$" or a imatches "X""#),
+        ("a like 'x*' and a glob '[ab]?'", r#"a like "x*" and a glob "[ab]?""#),
+        ("@ext = pdf and @mtime > 0", r#"@ext = "pdf" and @mtime > "0""#),
+        ("a in (b, 'c' \"d\")", r#"a in ("b", "c", "d")"#),
+        ("under '/a/b/' or directly in '/'", r#"under "/a/b" or directly in "/""#),
+        ("@q:unreviewed and not @q:old-2", "@q:unreviewed and not @q:old-2"),
+        ("any review-* = approved", r#"any review-* = "approved""#),
+        ("all review-? in (a b) or not any x*", r#"all review-? in ("a", "b") or not any x*"#),
+        ("a like x*", r#"a like "x*""#),
+        ("reviewed_version = $current_version", "reviewed_version = $current_version"),
+        ("end_date > $start_date and a is not $@name", "end_date > $start_date and a != $@name"),
+        ("a = '$b'", r#"a = "$b""#),
+        ("author near Jonathon", r#"author near "Jonathon""#),
+        ("author near 'Jonathon' within 2 and any x-* near y", r#"author near "Jonathon" within 2 and any x-* near "y""#),
+        ("parent.status = archived", r#"parent.status = "archived""#),
+        ("ancestor has confidential or not ancestor.confidential", "ancestor.confidential or not ancestor.confidential"),
+        ("Parent . a in (x) and parent.@name like 'a*'", r#"parent.a in ("x") and parent.@name like "a*""#),
+        ("rating > 1.5 and version = v2.0.1", r#"rating > "1.5" and version = "v2.0.1""#),
+        ("a && b || !c", "a and b or not c"),
+        ("a AND b Or NOT c", "a and b or not c"),
+        ("a IS NOT b and c != d and !(e)", r#"a != "b" and c != "d" and not (e)"#),
+        ("in-progress or order-id and not-done", "in-progress or order-id and not-done"),
+        ("Under '/a' OR DIRECTLY IN '/b'", r#"under "/a" or directly in "/b""#),
+        ("a = 'it\\'s'", r#"a = "it's""#),
+        (r#"a = "quote \" backslash \\ newline \n tab \t""#, r#"a = "quote \" backslash \\ newline \n tab \t""#),
+        (r#"a = "ünïcödé \u263a""#, r#"a = "ünïcödé ☺""#),
+        (r#"a = """#, r#"a = """#),
+        (r#""order" = 5 and "limit""#, r#""order" = "5" and "limit""#),
+        (r#"any "all*" = x or parent."has" in (y)"#, r#"any all* = "x" or parent."has" in ("y")"#),
+        (r#"parent has "Near" or 'a' = b or "parent" near c"#, r#"parent."Near" or a = "b" or "parent" near "c""#),
+    ];
+
+    for (input, canonical) in cases.iter() {
+        let q = parse_str(input);
+        assert_eq!(&q.to_string(), canonical);
+        assert_eq!(parse_str(&q.to_string()), q);
+    }
+}
+
+#[test]
+fn test_print_query_round_trip() {
+    let cases = [
+        ("a", "a"),
+        ("a order by b", "a order by b"),
+        ("a order by b asc, @size desc", "a order by b, @size desc"),
+        ("a or b order by c limit 10", "a or b order by c limit 10"),
+        ("a limit 10 offset 20", "a limit 10 offset 20"),
+        ("a offset 5", "a offset 5"),
+        (r#"a order by "limit" desc, 'offset'"#, r#"a order by "limit" desc, "offset""#),
+    ];
+
+    for (input, canonical) in cases.iter() {
+        let q = match parse_query_text(input) {
+            Ok(q) => q,
+            Err(e) => panic!("Failed to parse {}: {}", input, e)
+        };
+        assert_eq!(&q.to_string(), canonical);
+        assert_eq!(parse_query_text(&q.to_string()).ok(), Some(q));
+    }
+
+    for input in ["a order b", "a order by", "a limit", "a limit -1", "a limit x", "a offset 1 limit 1", "a order by @nope",
+                  "review-* = a", "any @name = x", "any", "a order by review-*",
+                  "a like $b", "a = $@nope", "any a-* = $b",
+                  "a near", "a near b within", "a near b within x",
+                  "parent", "parent status", "parent.", "parent has", "parent.review-*", "parent.@nope", "a.b = c", "ancestor has (a)",
+                  r#""a b" = c"#, r#""" = c"#, r#"any "x y""#, r#""review-*" = a"#, r#"a order by "@nope""#].iter() {
+        assert!(parse_query_text(input).is_err(), "{}", input);
+    }
+}
+
+#[test]
+fn test_parse_spread_arguments() {
+    use crate::cli::query::args::Args;
+
+    let parse_args = |argv: &[&str]| {
+        let args = Args::new(argv);
+        let query = (3..argv.len()).map(|i| args[i].clone()).into_vec();
+        let mut lexemes = match lex(&query, args.cmdline()) {
+            Ok(l) => l,
+            Err(_) => panic!("Failed to lex {}", args.cmdline())
+        };
+        match parse(&mut lexemes) {
+            Ok(q) => q,
+            Err(_) => panic!("Failed to parse {}", args.cmdline())
+        }
+    };
+    let text = |s: &str| parse_query_text(s).unwrap();
+
+    assert_eq!(parse_args(&["meta", "get", "where", "a", "=", "hello world", "&&", "(b", "||", "!c)"]),
+               text("a = 'hello world' && (b || !c)"));
+    assert_eq!(parse_args(&["meta", "get", "where", "a", "near", "it's", "or", "under", "/my docs"]),
+               text(r#"a near "it's" or under "/my docs""#));
+    assert_eq!(parse_args(&["meta", "get", "where", "a = b", "OR", "c"]),
+               text("(a = b) or c"));
+    assert_eq!(parse_args(&["meta", "get", "where", "a", "=", "or", "or", "b", "is", "not", "(draft)", "or", "c", "<", "$d"]),
+               text(r#"a = "or" or b != "(draft)" or c < $d"#));
+}
+
+#[test]
+fn test_query_keys() {
+    let keys = |s: &str| parse_query_text(s).unwrap().keys().into_iter().map(|k| k.to_owned()).into_vec();
+
+    assert_eq!(keys("a"), vec!["a"]);
+    assert_eq!(keys("a = x and (b or not c in (y, z)) or a near q order by d, b"), vec!["a", "b", "c", "d"]);
+    assert_eq!(keys("end > $start and @size > 10 and any review-* = ok"), vec!["end", "start"]);
+    assert_eq!(keys("@q:todo or under '/a' or @name = x"), Vec::<String>::new());
+    assert_eq!(keys("parent.client = acme and ancestor has b"), vec!["client", "b"]);
+}
+
+#[test]
+fn test_key_matches() {
+    let tests: &[(&str, &str, bool)] = &[
+        ("tmp-*", "tmp-1", true),
+        ("tmp-*", "tmp-", true),
+        ("tmp-*", "temp-1", false),
+        ("*-old", "a-b-old", true),
+        ("*-old", "a-old-b", false),
+        ("v?", "v2", true),
+        ("v?", "v", false),
+        ("*a*b*", "xaybz", true),
+        ("*a*b*", "xbya", false),
+        ("*", "", true),
+        ("exact", "exact", true),
+        ("exact", "Exact", false),
+    ];
+
+    for (pattern, key, exp) in tests {
+        assert_eq!(key_matches(pattern, key), *exp, "{} against {}", pattern, key);
+    }
+}
@@ -1,6 +1,6 @@
-use crate::cli::query::parse::OrQuery;
+use crate::cli::query::parse::Query;
 
 pub enum Selector {
     Files(Vec<String>),
-    Query(OrQuery)
+    Query(Query)
 }
\ No newline at end of file
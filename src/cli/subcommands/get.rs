@@ -1,4 +1,15 @@
-use crate::cli::args::{Flag, HELP_FLAG, Positional, QUIET_FLAG, RECURSIVE_FLAG, Subcommand, FileSelector};
+use std::collections::{BTreeMap, HashMap};
+use std::process::exit;
+use colored::{Color, Colorize};
+use serde::Serialize;
+use crate::cli::args::{check_query_keys, key_list, Flag, HELP_FLAG, KEY_RE, Positional, QUIET_FLAG, RECURSIVE_FLAG, Subcommand, SubcommandParseResults, FileEntryExpr, FileSelector, EXPLAIN_FLAG, STRICT_KEYS_FLAG, INHERITED_FLAG};
+use crate::cli::db::{absolute_paths, db_paths, entry_id, open_database, path_entries};
+use crate::cli::lang;
+use crate::cli::print::{log, print, write_out, Logger};
+use crate::cli::typo::typos_threshold;
+use crate::database::database::{Database, Entry};
+use crate::database::sqlite::{SqliteDatabase, SqliteError};
+use crate::linq::collectors::IntoVec;
 
 pub(crate) static SUBCOMMAND: Subcommand = Subcommand {
     name: "get",
@@ -9,6 +20,313 @@ pub(crate) static SUBCOMMAND: Subcommand = Subcommand {
         description: "The command will print the values for the given keys. If no keys are given, it will print all key/value pairs.",
     }),
     file_selector: FileSelector::FILE_LIST | FileSelector::QUERY,
-    flags: vec![HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG],
-    on_parse: |e| {},
-};
\ No newline at end of file
+    flags: vec![HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG, EXPLAIN_FLAG, STRICT_KEYS_FLAG, INHERITED_FLAG, FORMAT_FLAG],
+    on_parse: |e| run(e),
+};
+
+static FORMAT_FLAG: Flag = Flag {
+    aliases: vec!["--format", "-f"],
+    equals_name: Some("format"),
+    description: "How the key/value pairs are printed: text (the default), json, jsonl (a JSON object per line), toml, csv, tsv, or nul (each path, key and value followed by a NUL character). csv and tsv have a path,key,value row per pair.",
+};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Format {
+    Text,
+    Json,
+    JsonLines,
+    Toml,
+    Csv,
+    Tsv,
+    Nul,
+}
+
+static FORMATS: &[(&str, Format)] = &[
+    ("text", Format::Text),
+    ("json", Format::Json),
+    ("jsonl", Format::JsonLines),
+    ("toml", Format::Toml),
+    ("csv", Format::Csv),
+    ("tsv", Format::Tsv),
+    ("nul", Format::Nul),
+];
+
+/// The key/value pairs of an entry, in the order they're printed.
+struct EntryValues {
+    path: String,
+    /// (key, value, the path of the directory the value was inherited from)
+    values: Vec<(String, String, Option<String>)>,
+}
+
+/// How an entry is written out as JSON or TOML.
+#[derive(Serialize)]
+struct SerializedEntry<'a> {
+    path: &'a str,
+    metadata: BTreeMap<&'a str, &'a str>,
+    /// The directory each inherited value came from, keyed the same way as `metadata`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    inherited_from: BTreeMap<&'a str, &'a str>,
+}
+
+/// TOML has no top-level arrays, so the entries go in an array of tables named 'entry'.
+#[derive(Serialize)]
+struct TomlDocument<'a> {
+    entry: Vec<SerializedEntry<'a>>,
+}
+
+fn run(res: SubcommandParseResults) {
+    let quiet = res.has_flag(&QUIET_FLAG);
+    let inherited = res.inherited();
+    let format = parse_format(res.flag_value(&FORMAT_FLAG).unwrap_or("text"));
+
+    let args = res.positional().iter().map(|x| x.as_str()).into_vec();
+    let keys = match key_list(&args) {
+        Ok(k) => k.into_iter().filter(|k| !k.is_empty()).into_vec(),
+        Err(i) => {
+            log().error(&format!("The keys {} have two commas in a row.", args[i].bold().red()));
+            exit(1);
+        }
+    };
+
+    for k in &keys {
+        if !KEY_RE.is_match(k).unwrap_or(false) {
+            log().error(&format!("{} is not a valid key. Keys can only contain letters, numbers, '-' and '_'.", k.bold().red()));
+            exit(1);
+        }
+    }
+
+    let paths = match res.expr() {
+        Some(FileEntryExpr::List(p)) => Some(absolute_paths(p)),
+        _ => None
+    };
+
+    let db = open_database();
+    check_query_keys(&db, &res);
+
+    let entries = match (res.expr(), paths) {
+        (Some(FileEntryExpr::Expr(q)), _) => db.query_entries::<Vec<Entry>>(q),
+        (_, Some(p)) => path_entries(&db, &db_paths(&p), res.has_flag(&RECURSIVE_FLAG), quiet),
+        _ => {
+            log().error(&format!("Expected {} and a list of paths, or {} and a query, after the keys.", "from".bold().yellow(), "where".bold().yellow()));
+            exit(1);
+        }
+    };
+
+    let values = entries.and_then(|e| entry_values(&db, &e, &keys, inherited));
+
+    let values = match values {
+        Ok(v) => v,
+        Err(e) => {
+            log().error(&format!("Failed to get the metadata: {}", e));
+            exit(1);
+        }
+    };
+
+    match format {
+        Format::Text => {
+            if values.is_empty() && !quiet {
+                log().info("There are no matching files or directories.");
+            }
+
+            print_text(&values);
+        }
+        _ => write_out(&format_values(&values, format, inherited))
+    }
+}
+
+fn parse_format(name: &str) -> Format {
+    if let Some((_, f)) = FORMATS.iter().find(|x| x.0 == name.to_lowercase()) {
+        return *f;
+    }
+
+    let typos = typos_threshold(name, FORMATS.iter().map(|x| x.0), 0.5, 2);
+    let hint = if typos.is_empty() {
+        "".to_owned()
+    }
+    else {
+        let words = typos.iter().map(|x| x.0.bold().yellow().to_string()).into_vec();
+        format!(" Did you mean {}?", lang::or(words.iter().map(|x| x.as_str())))
+    };
+
+    log().error(&format!("Unknown format {}.{} The formats are {}.", name.bold().red(), hint, lang::and(FORMATS.iter().map(|x| x.0))));
+    exit(1);
+}
+
+/// Returns the key/value pairs of each entry, keeping only the given keys if there are any.
+fn entry_values(db: &SqliteDatabase, entries: &[Entry], keys: &[&str], inherited: bool) -> Result<Vec<EntryValues>, SqliteError> {
+    let mut metadata = HashMap::new();
+
+    if inherited {
+        for (e, kv) in db.entries_metadata_inherited::<Vec<_>, _>(entries.iter())? {
+            metadata.insert(entry_id(&e), kv.into_iter().map(|(k, v, d)| (k, v, d.map(|d| d.path))).into_vec());
+        }
+    }
+    else {
+        for (e, kv) in db.entries_metadata::<Vec<_>, _>(entries.iter())? {
+            metadata.insert(entry_id(&e), kv.into_iter().map(|(k, v)| (k, v, None)).into_vec());
+        }
+    }
+
+    let mut ret = Vec::new();
+
+    for e in entries {
+        let values = metadata.remove(&entry_id(e)).unwrap_or_default();
+        ret.push(EntryValues { path: db.entry_path(e)?, values: select_keys(values, keys) });
+    }
+
+    Ok(ret)
+}
+
+/// Keeps the pairs for the given keys in the order the keys were given, or every pair sorted by key if no keys were given.
+fn select_keys(mut values: Vec<(String, String, Option<String>)>, keys: &[&str]) -> Vec<(String, String, Option<String>)> {
+    if keys.is_empty() {
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        return values;
+    }
+
+    keys.iter()
+        .filter_map(|k| values.iter().position(|x| x.0 == *k))
+        .map(|i| values[i].clone())
+        .into_vec()
+}
+
+fn print_text(entries: &[EntryValues]) {
+    let mut p = print();
+
+    for e in entries {
+        let kv = e.values.iter().map(|(k, v, from)| (k.as_str(), v.as_str(), from.as_ref().map(|d| format!("from {}", d)))).into_vec();
+        p.key_values(&e.path, &kv, Color::Yellow);
+    }
+}
+
+/// Returns the output for every format except text, which goes through the PrintingContext instead.
+fn format_values(entries: &[EntryValues], format: Format, inherited: bool) -> String {
+    match format {
+        Format::Json => {
+            let serialized = entries.iter().map(serialized_entry).into_vec();
+            serde_json::to_string_pretty(&serialized).expect("Serializing the metadata to JSON should never fail.") + "\n"
+        }
+        Format::JsonLines => entries.iter()
+            .map(|e| serde_json::to_string(&serialized_entry(e)).expect("Serializing the metadata to JSON should never fail.") + "\n")
+            .collect(),
+        Format::Toml => {
+            let doc = TomlDocument { entry: entries.iter().map(serialized_entry).into_vec() };
+            toml::to_string(&doc).expect("Serializing the metadata to TOML should never fail.")
+        }
+        Format::Csv | Format::Tsv => {
+            let (separator, field): (&str, fn(&str) -> String) = match format {
+                Format::Csv => (",", csv_field),
+                _ => ("\t", tsv_field)
+            };
+
+            let mut header = vec!["path", "key", "value"];
+            if inherited {
+                header.push("inherited_from");
+            }
+
+            let mut ret = header.join(separator) + "\n";
+
+            for (path, k, v, from) in pairs(entries) {
+                let mut row = vec![field(path), field(k), field(v)];
+                if inherited {
+                    row.push(field(from.unwrap_or("")));
+                }
+
+                ret += &(row.join(separator) + "\n");
+            }
+
+            ret
+        }
+        Format::Nul => {
+            let mut ret = String::new();
+
+            for (path, k, v, from) in pairs(entries) {
+                for f in &[path, k, v] {
+                    ret += f;
+                    ret.push('\0');
+                }
+
+                if inherited {
+                    ret += from.unwrap_or("");
+                    ret.push('\0');
+                }
+            }
+
+            ret
+        }
+        Format::Text => unreachable!("Text is printed through the PrintingContext.")
+    }
+}
+
+fn serialized_entry(e: &EntryValues) -> SerializedEntry<'_> {
+    SerializedEntry {
+        path: &e.path,
+        metadata: e.values.iter().map(|(k, v, _)| (k.as_str(), v.as_str())).collect(),
+        inherited_from: e.values.iter().filter_map(|(k, _, from)| from.as_ref().map(|d| (k.as_str(), d.as_str()))).collect(),
+    }
+}
+
+/// Returns every (path, key, value, inherited from) of the entries, in order.
+fn pairs(entries: &[EntryValues]) -> Vec<(&str, &str, &str, Option<&str>)> {
+    entries.iter()
+        .flat_map(|e| e.values.iter().map(move |(k, v, from)| (e.path.as_str(), k.as_str(), v.as_str(), from.as_deref())))
+        .into_vec()
+}
+
+/// Quotes a CSV field if it needs to be, doubling any quotes inside it.
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    }
+    else {
+        s.to_owned()
+    }
+}
+
+/// Escapes the characters a TSV field can't contain, along with backslashes so the escapes can be told apart.
+fn tsv_field(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+#[test]
+fn test_format_values() {
+    let entries = vec![
+        EntryValues { path: "/a".to_owned(), values: vec![("client".to_owned(), "acme, inc".to_owned(), None)] },
+        EntryValues { path: "/a/x.txt".to_owned(), values: vec![
+            ("client".to_owned(), "acme, inc".to_owned(), Some("/a".to_owned())),
+            ("note".to_owned(), "say \"hi\"\tthen\nleave".to_owned(), None),
+        ] },
+        EntryValues { path: "/b".to_owned(), values: vec![] },
+    ];
+
+    assert_eq!(format_values(&entries, Format::Csv, false), concat!(
+        "path,key,value\n",
+        "/a,client,\"acme, inc\"\n",
+        "/a/x.txt,client,\"acme, inc\"\n",
+        "/a/x.txt,note,\"say \"\"hi\"\"\tthen\nleave\"\n",
+    ));
+
+    assert_eq!(format_values(&entries, Format::Tsv, true), concat!(
+        "path\tkey\tvalue\tinherited_from\n",
+        "/a\tclient\tacme, inc\t\n",
+        "/a/x.txt\tclient\tacme, inc\t/a\n",
+        "/a/x.txt\tnote\tsay \"hi\"\\tthen\\nleave\t\n",
+    ));
+
+    assert_eq!(format_values(&entries[..1], Format::Nul, false), "/a\0client\0acme, inc\0");
+
+    assert_eq!(format_values(&entries[1..], Format::JsonLines, true), concat!(
+        r#"{"path":"/a/x.txt","metadata":{"client":"acme, inc","note":"say \"hi\"\tthen\nleave"},"inherited_from":{"client":"/a"}}"#, "\n",
+        r#"{"path":"/b","metadata":{}}"#, "\n",
+    ));
+
+    let toml = format_values(&entries, Format::Toml, true);
+    let parsed = toml.parse::<toml::Value>().unwrap();
+    assert_eq!(parsed["entry"][1]["path"].as_str(), Some("/a/x.txt"));
+    assert_eq!(parsed["entry"][1]["metadata"]["note"].as_str(), Some("say \"hi\"\tthen\nleave"));
+    assert_eq!(parsed["entry"][1]["inherited_from"]["client"].as_str(), Some("/a"));
+
+    let json = format_values(&entries, Format::Json, false).parse::<serde_json::Value>().unwrap();
+    assert_eq!(json[0]["metadata"]["client"], "acme, inc");
+    assert_eq!(json.as_array().map(|x| x.len()), Some(3));
+}
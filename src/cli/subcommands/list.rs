@@ -1,14 +1,140 @@
-use crate::cli::args::{Flag, HELP_FLAG, Positional, QUIET_FLAG, RECURSIVE_FLAG, Subcommand};
+use std::collections::HashMap;
+use std::process::exit;
+use colored::Colorize;
+use crate::cli::args::{check_query_keys, key_list, Flag, HELP_FLAG, KEY_RE, QUIET_FLAG, RECURSIVE_FLAG, Subcommand, SubcommandParseResults, FileEntryExpr, EXPLAIN_FLAG, STRICT_KEYS_FLAG, INHERITED_FLAG, FileSelector};
+use crate::cli::db::{absolute_paths, db_paths, entry_id, open_database, path_entries};
+use crate::cli::print::{log, print, write_out, Logger, TableColumn};
+use crate::cli::query::parse::Query;
+use crate::database::database::{Database, Entry};
+use crate::database::sqlite::{SqliteDatabase, SqliteError};
+use crate::linq::collectors::IntoVec;
 
 pub(crate) static SUBCOMMAND: Subcommand = Subcommand {
     name: "list",
     description: "Lists files matching an expression.",
-    positional: Some(Positional {
-        name: "([key,]*key)?",
-        count: (None, None),
-        description: "The command will print the values for the given keys. If no keys are given, it will print all key/value pairs.",
-    }),
-    file_entry_expr: true,
-    flags: vec![HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG],
-    on_parse: |e| {},
-};
\ No newline at end of file
+    positional: None,
+    file_selector: FileSelector::FILE_LIST | FileSelector::QUERY,
+    flags: vec![HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG, EXPLAIN_FLAG, STRICT_KEYS_FLAG, INHERITED_FLAG, COLUMNS_FLAG, NO_HEADER_FLAG, PATHS_ONLY_FLAG],
+    on_parse: |e| run(e),
+};
+
+static COLUMNS_FLAG: Flag = Flag {
+    aliases: vec!["--columns", "-c"],
+    equals_name: Some("key1,key2,..."),
+    description: "Prints a table with a column for the value of each of the keys, after the path.",
+};
+
+static NO_HEADER_FLAG: Flag = Flag {
+    aliases: vec!["--no-header"],
+    equals_name: None,
+    description: "Leaves out the row naming the columns.",
+};
+
+static PATHS_ONLY_FLAG: Flag = Flag {
+    aliases: vec!["--paths-only"],
+    equals_name: None,
+    description: "Prints only the paths, one per line and without any formatting, so they can be piped into other commands.",
+};
+
+fn run(res: SubcommandParseResults) {
+    let quiet = res.has_flag(&QUIET_FLAG);
+    let inherited = res.inherited();
+    let paths_only = res.has_flag(&PATHS_ONLY_FLAG);
+
+    let keys = match res.flag_value(&COLUMNS_FLAG) {
+        Some(v) => match key_list(&[v]) {
+            Ok(k) => k.into_iter().filter(|k| !k.is_empty()).into_vec(),
+            Err(_) => {
+                log().error(&format!("The columns {} have two commas in a row.", v.bold().red()));
+                exit(1);
+            }
+        },
+        None => Vec::new()
+    };
+
+    for k in &keys {
+        if !KEY_RE.is_match(k).unwrap_or(false) {
+            log().error(&format!("{} is not a valid key. Keys can only contain letters, numbers, '-' and '_'.", k.bold().red()));
+            exit(1);
+        }
+    }
+
+    if paths_only && !keys.is_empty() {
+        log().error(&format!("{} prints nothing but the paths, so it can't be used with {}.", "--paths-only".bold().yellow(), "--columns".bold().yellow()));
+        exit(1);
+    }
+
+    let paths = match res.expr() {
+        Some(FileEntryExpr::List(p)) => Some(absolute_paths(p)),
+        _ => None
+    };
+
+    let db = open_database();
+    check_query_keys(&db, &res);
+
+    let entries = match (res.expr(), paths) {
+        (Some(FileEntryExpr::Expr(q)), _) => db.query_entries::<Vec<Entry>>(q),
+        (_, Some(p)) => path_entries(&db, &db_paths(&p), res.has_flag(&RECURSIVE_FLAG), quiet),
+        _ => db.query_entries::<Vec<Entry>>(&Query::everything(inherited))
+    };
+
+    let rows = entries.and_then(|e| entry_rows(&db, &e, &keys, inherited));
+
+    let rows = match rows {
+        Ok(r) => r,
+        Err(e) => {
+            log().error(&format!("Failed to list the files and directories: {}", e));
+            exit(1);
+        }
+    };
+
+    if paths_only {
+        write_out(&rows.iter().map(|r| r[0].clone() + "\n").collect::<String>());
+        return;
+    }
+
+    if rows.is_empty() {
+        if !quiet {
+            log().info("There are no matching files or directories.");
+        }
+        return;
+    }
+
+    let columns = std::iter::once(TableColumn { header: "path", truncate_start: true })
+        .chain(keys.iter().map(|k| TableColumn { header: k, truncate_start: false }))
+        .into_vec();
+
+    print().table(&columns, &rows, !res.has_flag(&NO_HEADER_FLAG));
+}
+
+/// Returns a row for each entry: its path, followed by its value for each of the keys, or an empty string if it doesn't have the key.
+fn entry_rows(db: &SqliteDatabase, entries: &[Entry], keys: &[&str], inherited: bool) -> Result<Vec<Vec<String>>, SqliteError> {
+    let mut metadata = HashMap::<(bool, i32), Vec<(String, String)>>::new();
+
+    // without any columns, only the paths are needed
+    if !keys.is_empty() && inherited {
+        for (e, kv) in db.entries_metadata_inherited::<Vec<_>, _>(entries.iter())? {
+            metadata.insert(entry_id(&e), kv.into_iter().map(|(k, v, _)| (k, v)).into_vec());
+        }
+    }
+    else if !keys.is_empty() {
+        for (e, kv) in db.entries_metadata::<Vec<_>, _>(entries.iter())? {
+            metadata.insert(entry_id(&e), kv);
+        }
+    }
+
+    let mut ret = Vec::new();
+
+    for e in entries {
+        let kv = metadata.remove(&entry_id(e)).unwrap_or_default();
+
+        let mut row = vec![db.entry_path(e)?];
+        for k in keys {
+            row.push(kv.iter().find(|x| x.0 == *k).map(|x| x.1.clone()).unwrap_or_default());
+        }
+
+        ret.push(row);
+    }
+
+    Ok(ret)
+}
@@ -2,3 +2,5 @@ pub mod get;
 pub mod set;
 pub mod remove;
 pub mod list;
+pub mod query;
+pub mod stats;
@@ -0,0 +1,169 @@
+use std::process::exit;
+use colored::Colorize;
+use crate::cli::args::{FileSelector, HELP_FLAG, KEY_RE, Positional, QUIET_FLAG, Subcommand, SubcommandParseResults};
+use crate::cli::db::open_database;
+use crate::cli::print::{log, print, Logger};
+use crate::cli::query::diagnostic::{report_lex_error, report_parse_error};
+use crate::cli::query::lex::lex;
+use crate::cli::query::parse::{parse_filter, parse_text, OrQuery};
+use crate::database::database::Database;
+use crate::database::models::SavedQuery;
+use crate::database::query::{expand_saved_queries, saved_query_references};
+use crate::database::sqlite::SqliteDatabase;
+use crate::linq::collectors::IntoVec;
+
+pub(crate) static SUBCOMMAND: Subcommand = Subcommand {
+    name: "query",
+    description: "Saves, lists, shows and deletes named queries. A saved query can be used in a 'where' query as @q:name.",
+    positional: Some(Positional {
+        name: "save name query | list | show name | delete name",
+        count: (Some(1), None),
+        description: "save stores the query under the name, replacing any query already saved under it. The query is either a single quoted argument or every argument after a '--'. list prints every saved query, show prints the query saved under the name, and delete removes it.",
+    }),
+    file_selector: FileSelector::NONE,
+    flags: vec![HELP_FLAG, QUIET_FLAG],
+    on_parse: |e| run(e),
+};
+
+fn run(res: SubcommandParseResults) {
+    let args = res.positional();
+    let quiet = res.has_flag(&QUIET_FLAG);
+    let db = open_database();
+
+    match (args[0].as_str(), &args[1..]) {
+        ("save", [name, query @ ..]) if !query.is_empty() => save(&db, name, &parse_query(&res), quiet),
+        ("list", []) => list(&db),
+        ("show", [name]) => show(&db, name),
+        ("delete", [name]) => delete(&db, name, quiet),
+        _ => {
+            log().error(&format!("Expected {}, {}, {} or {}.", "save name query".bold().yellow(), "list".bold().yellow(), "show name".bold().yellow(), "delete name".bold().yellow()));
+            exit(1);
+        }
+    }
+}
+
+/// Lexes and parses the arguments after 'save name' the same way the arguments after 'where' are.
+fn parse_query(res: &SubcommandParseResults) -> OrQuery {
+    let args = res.positional_args(2);
+
+    let mut lexemes = match lex(&args, res.cmdline()) {
+        Ok(l) => l,
+        Err(e) => {
+            report_lex_error(&e, res.cmdline());
+            exit(1);
+        }
+    };
+
+    match parse_filter(&mut lexemes) {
+        Ok(q) => q,
+        Err(e) => {
+            report_parse_error(&e, res.cmdline());
+            exit(1);
+        }
+    }
+}
+
+fn save(db: &SqliteDatabase, name: &str, query: &OrQuery, quiet: bool) {
+    if !KEY_RE.is_match(name).unwrap_or(false) {
+        log().error(&format!("The name {} is not valid. A saved query's name can only contain letters, numbers, '-' and '_'.", name.bold().red()));
+        exit(1);
+    }
+
+    // stored in canonical form so it reads the same no matter how it was typed
+    let canonical = query.to_string();
+
+    // make sure every saved query it refers to exists, and that none of them lead back to this one
+    let checked = expand_saved_queries(query, |n| {
+        if n == name {
+            Ok(Some(canonical.clone()))
+        }
+        else {
+            Ok(db.saved_query(n)?.map(|q| q.query))
+        }
+    });
+
+    if let Err(e) = checked {
+        log().error(&format!("The query {} can't be saved: {}", canonical.bold().red(), e));
+        exit(1);
+    }
+
+    match db.save_query(name, &canonical) {
+        Ok(replaced) => if !quiet {
+            log().info(&format!("{} the query {} as {}.", if replaced { "Replaced" } else { "Saved" }, canonical, format!("@q:{}", name).bold().yellow()));
+        },
+        Err(e) => {
+            log().error(&format!("Failed to save the query: {}", e));
+            exit(1);
+        }
+    }
+}
+
+fn list(db: &SqliteDatabase) {
+    let queries = match db.saved_queries::<Vec<SavedQuery>>() {
+        Ok(q) => q,
+        Err(e) => {
+            log().error(&format!("Failed to get the saved queries: {}", e));
+            exit(1);
+        }
+    };
+
+    if queries.is_empty() {
+        log().info("There are no saved queries.");
+        return;
+    }
+
+    let width = queries.iter().map(|q| q.name.chars().count()).max().unwrap_or(0) + 3;
+
+    for q in queries {
+        print().preformatted(&format!("{:<w$}  {}", format!("@q:{}", q.name).bold().yellow(), q.query, w = width));
+    }
+}
+
+fn show(db: &SqliteDatabase, name: &str) {
+    match db.saved_query(name) {
+        Ok(Some(q)) => print().preformatted(&q.query),
+        Ok(None) => {
+            log().error(&format!("There is no saved query named {}.", name.bold().red()));
+            exit(1);
+        }
+        Err(e) => {
+            log().error(&format!("Failed to get the saved query: {}", e));
+            exit(1);
+        }
+    }
+}
+
+fn delete(db: &SqliteDatabase, name: &str, quiet: bool) {
+    let queries = match db.saved_queries::<Vec<SavedQuery>>() {
+        Ok(q) => q,
+        Err(e) => {
+            log().error(&format!("Failed to get the saved queries: {}", e));
+            exit(1);
+        }
+    };
+
+    // deleting a saved query that another one refers to would leave that one unusable
+    let referrers = queries.iter()
+        .filter(|q| q.name != name && parse_text(&q.query).map(|p| saved_query_references(&p).contains(&name)).unwrap_or(false))
+        .map(|q| format!("@q:{}", q.name).bold().yellow().to_string())
+        .into_vec();
+
+    if !referrers.is_empty() {
+        log().error(&format!("The saved query {} can't be deleted because it is used by {}. Delete or change those first.", name.bold().red(), referrers.join(", ")));
+        exit(1);
+    }
+
+    match db.delete_saved_query(name) {
+        Ok(true) => if !quiet {
+            log().info(&format!("Deleted the saved query {}.", format!("@q:{}", name).bold().yellow()));
+        },
+        Ok(false) => {
+            log().error(&format!("There is no saved query named {}.", name.bold().red()));
+            exit(1);
+        }
+        Err(e) => {
+            log().error(&format!("Failed to delete the saved query: {}", e));
+            exit(1);
+        }
+    }
+}
@@ -1,19 +1,170 @@
-use crate::cli::args::{Flag, HELP_FLAG, QUIET_FLAG, Subcommand, Positional, RECURSIVE_FLAG};
+use std::collections::HashMap;
+use std::process::exit;
+use colored::{Color, Colorize};
+use crate::cli::args::{check_query_keys, key_list, FileEntryExpr, Flag, HELP_FLAG, QUIET_FLAG, Subcommand, SubcommandParseResults, Positional, RECURSIVE_FLAG, EXPLAIN_FLAG, STRICT_KEYS_FLAG, FileSelector};
+use crate::cli::db::{absolute_paths, db_paths, entry_id, open_database, path_entries};
+use crate::cli::print::{log, print, Logger};
+use crate::cli::query::parse::{is_key_pattern, key_matches};
+use crate::database::database::{Database, Entry};
+use crate::database::sqlite::{SqliteDatabase, SqliteError};
+use crate::linq::collectors::IntoVec;
 
 pub static SUBCOMMAND: Subcommand = Subcommand {
     name: "remove",
     description: "Removes metadata.",
     positional: Some(Positional {
         name: "([key,]*key)*",
-        count: (Some(1), None),
-        description: "The command will remove the given keys.",
+        count: (None, None),
+        description: "The command will remove the given keys. A key can also be a pattern like 'tmp-*', where '*' matches any run of characters and '?' matches exactly one, which removes every key matching it.",
     }
     ),
-    file_entry_expr: true,
-    flags: vec![HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG, Flag {
-        aliases: vec!["--all", "-a"]
-        equals_name: None,
-        description: "Removes all of the keys from the given targets.",
-    }],
-    on_parse: |_| {}
-};
\ No newline at end of file
+    file_selector: FileSelector::FILE_LIST | FileSelector::QUERY,
+    flags: vec![HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG, EXPLAIN_FLAG, STRICT_KEYS_FLAG, ALL_FLAG],
+    on_parse: |e| run(e)
+};
+
+static ALL_FLAG: Flag = Flag {
+    aliases: vec!["--all", "-a"],
+    equals_name: None,
+    description: "Removes all of the keys from the given targets.",
+};
+
+fn run(res: SubcommandParseResults) {
+    let quiet = res.has_flag(&QUIET_FLAG);
+    let all = res.has_flag(&ALL_FLAG);
+
+    let args = res.positional().iter().map(|x| x.as_str()).into_vec();
+    let keys = match key_list(&args) {
+        Ok(k) => k.into_iter().filter(|k| !k.is_empty()).into_vec(),
+        Err(i) => {
+            log().error(&format!("The keys {} have two commas in a row.", args[i].bold().red()));
+            exit(1);
+        }
+    };
+
+    match (all, keys.is_empty()) {
+        (true, false) => {
+            log().error(&format!("{} removes every key, so it can't be given keys as well.", "--all".bold().yellow()));
+            exit(1);
+        }
+        (false, true) => {
+            log().error(&format!("Expected the keys to remove, or {} to remove every key.", "--all".bold().yellow()));
+            exit(1);
+        }
+        _ => {}
+    }
+
+    for k in &keys {
+        if !k.chars().all(|c| c.is_ascii_alphanumeric() || "-_*?".contains(c)) {
+            log().error(&format!("{} is not a valid key. Keys can only contain letters, numbers, '-' and '_', along with '*' and '?' in a pattern.", k.bold().red()));
+            exit(1);
+        }
+    }
+
+    let paths = match res.expr() {
+        Some(FileEntryExpr::List(p)) => Some(absolute_paths(p)),
+        Some(FileEntryExpr::Expr(_)) => None,
+        None => {
+            log().error(&format!("Expected {} and a list of paths, or {} and a query, after the keys.", "from".bold().yellow(), "where".bold().yellow()));
+            exit(1);
+        }
+    };
+
+    let db = open_database();
+    check_query_keys(&db, &res);
+    let paths = paths.map(|p| db_paths(&p));
+
+    let removed = db.transaction(|| {
+        let entries = match (res.expr(), &paths) {
+            (Some(FileEntryExpr::Expr(q)), _) => db.query_entries::<Vec<Entry>>(q)?,
+            (_, Some(p)) => path_entries(&db, p, res.has_flag(&RECURSIVE_FLAG), quiet)?,
+            _ => unreachable!("A missing file list or query exits above.")
+        };
+
+        let removed = if all { remove_all(&db, &entries)? } else { remove_keys(&db, &entries, &keys)? };
+
+        removed.into_iter()
+            .map(|(e, kv)| Ok((db.entry_path(&e)?, kv)))
+            .collect::<Result<Vec<_>, SqliteError>>()
+    });
+
+    let removed = match removed {
+        Ok(r) => r,
+        Err(e) => {
+            log().error(&format!("Failed to remove the metadata, so nothing was changed: {}", e));
+            exit(1);
+        }
+    };
+
+    if quiet {
+        return;
+    }
+
+    if removed.is_empty() {
+        log().info("None of the files or directories had anything to remove.");
+        return;
+    }
+
+    print_removed(&removed);
+
+    let count = removed.iter().map(|x| x.1.len()).sum::<usize>();
+    log().info(&format!("Removed {} key/value {} from {} {}.",
+                        count, if count == 1 { "pair" } else { "pairs" },
+                        removed.len(), if removed.len() == 1 { "entry" } else { "entries" }));
+}
+
+/// Clears every key from the entries, returning each entry that had any along with its old key/value pairs.
+fn remove_all(db: &SqliteDatabase, entries: &[Entry]) -> Result<Vec<(Entry, Vec<(String, String)>)>, SqliteError> {
+    let mut old = db.entries_metadata::<Vec<_>, _>(entries.iter())?.into_iter()
+        .map(|(e, kv)| (entry_id(&e), kv))
+        .collect::<HashMap<_, _>>();
+
+    db.entries_metadata_clear(entries.iter())?;
+
+    Ok(in_entry_order(entries, &mut old))
+}
+
+/// Removes the keys and the keys matching the patterns from the entries, returning each entry that had any along with the pairs it lost.
+fn remove_keys(db: &SqliteDatabase, entries: &[Entry], keys: &[&str]) -> Result<Vec<(Entry, Vec<(String, String)>)>, SqliteError> {
+    let (patterns, names): (Vec<&str>, Vec<&str>) = keys.iter().copied().partition(|k| is_key_pattern(k));
+    let mut removed = HashMap::<(bool, i32), Vec<(String, String)>>::new();
+
+    for k in names {
+        for (e, old) in db.entries_metadata_set::<Vec<_>, _>(entries.iter(), k, None)? {
+            if let Some(v) = old {
+                removed.entry(entry_id(&e)).or_default().push((k.to_owned(), v));
+            }
+        }
+    }
+
+    if !patterns.is_empty() {
+        for (e, kv) in db.entries_metadata::<Vec<_>, _>(entries.iter())? {
+            for (k, _) in kv.into_iter().filter(|(k, _)| patterns.iter().any(|p| key_matches(p, k))) {
+                if let Some(v) = db.entry_metadata_set(&e, &k, None)? {
+                    removed.entry(entry_id(&e)).or_default().push((k, v));
+                }
+            }
+        }
+    }
+
+    Ok(in_entry_order(entries, &mut removed))
+}
+
+/// Pairs each entry that lost key/value pairs with them, sorted by key, in the order the entries were given.
+fn in_entry_order(entries: &[Entry], removed: &mut HashMap<(bool, i32), Vec<(String, String)>>) -> Vec<(Entry, Vec<(String, String)>)> {
+    entries.iter()
+        .filter_map(|e| removed.remove(&entry_id(e)).filter(|kv| !kv.is_empty()).map(|mut kv| {
+            kv.sort_by(|a, b| a.0.cmp(&b.0));
+            (e.clone(), kv)
+        }))
+        .into_vec()
+}
+
+fn print_removed(removed: &[(String, Vec<(String, String)>)]) {
+    let mut p = print();
+
+    for (path, kv) in removed {
+        let kv = kv.iter().map(|(k, v)| (k.as_str(), v.as_str(), None)).into_vec();
+        p.key_values(path, &kv, Color::Red);
+    }
+}
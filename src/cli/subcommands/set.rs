@@ -1,4 +1,18 @@
-use crate::cli::args::{Positional, Subcommand, HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG};
+use std::collections::HashSet;
+use std::io::{stdin, Read};
+use std::path::PathBuf;
+use std::process::exit;
+use colored::Colorize;
+use walkdir::WalkDir;
+use crate::cli::args::{check_query_keys, ASSIGN_RE, FileEntryExpr, Positional, Subcommand, SubcommandParseResults, HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG, EXPLAIN_FLAG, STRICT_KEYS_FLAG, FileSelector};
+use crate::cli::db::{absolute_paths, db_paths, entry_id, open_database};
+use crate::cli::lang;
+use crate::cli::print::{log, Logger};
+use crate::database::database::{Database, Entry};
+use crate::database::sqlite::{SqliteDatabase, SqliteError};
+use crate::database::sqlite::SqliteError::ApplicationError;
+use crate::filesystem::fs::{entry_metadata, file_hash, DB_NAME};
+use crate::linq::collectors::IntoVec;
 
 pub(crate) static SUBCOMMAND: Subcommand = Subcommand {
     name: "set",
@@ -6,9 +20,253 @@ pub(crate) static SUBCOMMAND: Subcommand = Subcommand {
     positional: Some(Positional {
         name: "(key=value)+",
         count: (Some(1), None),
-        description: "One or more key=value assignments, meaning assign the value to the key.",
+        description: "One or more key=value assignments, meaning assign the value to the key. A value of - is read from stdin, and a value of @path is read from the file at that path, in both cases without the newline at the end. A value starting with @@ is taken as-is, minus the first @. Paths that aren't in the database yet are added to it.",
     }),
-    file_entry_expr: true,
-    flags: vec![HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG],
-    on_parse: |_| {}
-};
\ No newline at end of file
+    file_selector: FileSelector::FILE_LIST | FileSelector::QUERY,
+    flags: vec![HELP_FLAG, QUIET_FLAG, RECURSIVE_FLAG, EXPLAIN_FLAG, STRICT_KEYS_FLAG],
+    on_parse: |e| run(e),
+};
+
+fn run(res: SubcommandParseResults) {
+    let quiet = res.has_flag(&QUIET_FLAG);
+
+    // read before open_database, since the paths of @path values are relative to the current directory
+    let assignments = read_assignments(res.positional());
+
+    let paths = match res.expr() {
+        Some(FileEntryExpr::List(p)) => Some(walk_paths(&absolute_paths(p), res.has_flag(&RECURSIVE_FLAG))),
+        Some(FileEntryExpr::Expr(_)) => None,
+        None => {
+            log().error(&format!("Expected {} and a list of paths, or {} and a query, after the assignments.", "from".bold().yellow(), "where".bold().yellow()));
+            exit(1);
+        }
+    };
+
+    let db = open_database();
+    check_query_keys(&db, &res);
+    let paths = paths.map(|p| db_paths(&p));
+
+    // every assignment is made or none of them are
+    let result = db.transaction(|| {
+        let (entries, added) = match (res.expr(), &paths) {
+            (Some(FileEntryExpr::Expr(q)), _) => (db.query_entries::<Vec<Entry>>(q)?, 0),
+            (_, Some(p)) => register_paths(&db, p, res.has_flag(&RECURSIVE_FLAG))?,
+            _ => unreachable!("A missing file list or query exits above.")
+        };
+
+        for (k, v) in &assignments {
+            db.entries_metadata_set::<Vec<_>, _>(entries.iter(), k, Some(v))?;
+        }
+
+        Ok((entries.len(), added))
+    });
+
+    match result {
+        Ok((count, added)) => if !quiet {
+            if added > 0 {
+                log().info(&format!("Added {} new {} to the database.", added, if added == 1 { "entry" } else { "entries" }));
+            }
+
+            let keys = assignments.iter().map(|x| x.0.bold().yellow().to_string()).into_vec();
+            log().info(&format!("Set {} on {} {}.", lang::and(keys.iter().map(|x| x.as_str())), count, if count == 1 { "entry" } else { "entries" }));
+        },
+        Err(e) => {
+            log().error(&format!("Failed to set the values, so nothing was changed: {}", e));
+            exit(1);
+        }
+    }
+}
+
+/// Parses the key=value assignments, reading the values that come from stdin or a file.
+fn read_assignments(args: &[String]) -> Vec<(String, String)> {
+    let mut ret = Vec::<(String, String)>::new();
+    let mut read_stdin = false;
+
+    for arg in args {
+        let caps = match ASSIGN_RE.captures(arg) {
+            Ok(Some(c)) => c,
+            _ => {
+                log().error(&format!("{} is not a key=value assignment. Keys can only contain letters, numbers, '-' and '_', and values can't be empty.", arg.bold().red()));
+                exit(1);
+            }
+        };
+
+        let key = caps.get(1).unwrap().as_str();
+        let raw = caps.get(2).unwrap().as_str();
+
+        if ret.iter().any(|x| x.0 == key) {
+            log().error(&format!("{} is assigned more than once.", key.bold().red()));
+            exit(1);
+        }
+
+        let value = if raw == "-" {
+            if read_stdin {
+                log().error(&format!("Only one value can be read from stdin, but {} is the second.", key.bold().red()));
+                exit(1);
+            }
+            read_stdin = true;
+
+            let mut s = String::new();
+            if let Err(e) = stdin().read_to_string(&mut s) {
+                log().error(&format!("Failed to read the value of {} from stdin: {}", key.bold().yellow(), e));
+                exit(1);
+            }
+
+            trim_newline(s)
+        }
+        else if raw.starts_with("@@") {
+            raw[1..].to_owned()
+        }
+        else if raw.starts_with('@') {
+            match std::fs::read_to_string(&raw[1..]) {
+                Ok(s) => trim_newline(s),
+                Err(e) => {
+                    log().error(&format!("Failed to read the value of {} from {}: {}", key.bold().yellow(), raw[1..].bold().red(), e));
+                    exit(1);
+                }
+            }
+        }
+        else {
+            raw.to_owned()
+        };
+
+        ret.push((key.to_owned(), value));
+    }
+
+    ret
+}
+
+/// Removes the one newline at the end of a value, which files and most commands piped into stdin end with.
+fn trim_newline(mut s: String) -> String {
+    if s.ends_with('\n') {
+        s.pop();
+
+        if s.ends_with('\r') {
+            s.pop();
+        }
+    }
+
+    s
+}
+
+/// Returns the paths along with, if `recursive`, every file and directory below them.
+fn walk_paths(paths: &[PathBuf], recursive: bool) -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+
+    for p in paths {
+        ret.push(p.clone());
+
+        if !recursive || !p.is_dir() {
+            continue;
+        }
+
+        for e in WalkDir::new(p).min_depth(1) {
+            let e = match e {
+                Ok(e) => e,
+                Err(e) => {
+                    log().error(&format!("Failed to read {}: {}", p.to_string_lossy().bold().red(), e));
+                    exit(1);
+                }
+            };
+
+            // symlinks aren't followed, and the database shouldn't be tagged along with everything else
+            if (e.file_type().is_file() || e.file_type().is_dir()) && e.file_name() != DB_NAME {
+                ret.push(e.into_path());
+            }
+        }
+    }
+
+    ret
+}
+
+/// Returns the entries at the given paths, adding the ones that aren't in the database yet, along with how many were added.
+///
+/// If `recursive`, the entries already in the database below each directory are included, even if they're no longer on disk.
+fn register_paths(db: &SqliteDatabase, paths: &[String], recursive: bool) -> Result<(Vec<Entry>, usize), SqliteError> {
+    let mut ret = Vec::new();
+    let mut seen = HashSet::new();
+    let mut added = 0;
+
+    for p in paths {
+        let entry = match db.get_entry(p)? {
+            Some(e) => e,
+            None => {
+                let meta = entry_metadata(p).map_err(|e| ApplicationError(format!("Can't add '{}' to the database: {}", p, e)))?;
+
+                added += 1;
+
+                if meta.is_dir() {
+                    Entry::Directory(db.add_directory(p)?.0)
+                }
+                else {
+                    let hash = file_hash(p).map_err(|e| ApplicationError(format!("Failed to read '{}': {}", p, e)))?;
+                    Entry::File(db.add_file(p, &hash)?.0)
+                }
+            }
+        };
+
+        let below = match &entry {
+            Entry::Directory(d) if recursive => db.directory_entries::<Vec<Entry>>(d)?,
+            _ => Vec::new()
+        };
+
+        for e in std::iter::once(entry).chain(below) {
+            if seen.insert(entry_id(&e)) {
+                ret.push(e);
+            }
+        }
+    }
+
+    Ok((ret, added))
+}
+
+#[test]
+fn test_trim_newline() {
+    assert_eq!(trim_newline("acme\n".to_owned()), "acme");
+    assert_eq!(trim_newline("acme\r\n".to_owned()), "acme");
+    assert_eq!(trim_newline("line one\nline two\n\n".to_owned()), "line one\nline two\n");
+    assert_eq!(trim_newline("acme".to_owned()), "acme");
+}
+
+#[test]
+fn test_register_paths() {
+    use std::env::{current_dir, set_current_dir, temp_dir};
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use crate::cli::db::db_paths;
+
+    // stored paths are relative to the directory containing the database, which open_database moves into
+    let root = temp_dir().join(format!("meta-test-register-paths-{}", std::process::id()));
+    create_dir_all(root.join("docs/sub")).unwrap();
+    for f in &["top.txt", "new.txt", "docs/a.txt", "docs/sub/a.txt"] {
+        write(root.join(f), f).unwrap();
+    }
+    let root = root.canonicalize().unwrap();
+    let cwd = current_dir().unwrap();
+    set_current_dir(&root).unwrap();
+
+    let db = SqliteDatabase::new(":memory:").ok().expect("Failed to open an in-memory database.");
+    let paths = |entries: &[Entry]| entries.iter().map(|e| db.entry_path(e).ok().expect("Failed to get the path of an entry.")).into_vec();
+
+    // paths that aren't in the database are added, and a path given twice is only returned once
+    let (entries, added) = register_paths(&db, &["/top.txt".to_owned(), "/docs".to_owned(), "/top.txt".to_owned()], false).ok().expect("Failed to register the paths.");
+    assert_eq!(paths(&entries), vec!["/top.txt", "/docs"]);
+    assert_eq!(added, 2);
+    assert!(matches!(db.get_entry("/top.txt").ok().expect("Failed to look up /top.txt."), Some(Entry::File(f)) if f.hash == file_hash("/top.txt").unwrap()));
+
+    let (_, added) = register_paths(&db, &["/top.txt".to_owned()], false).ok().expect("Failed to register the paths again.");
+    assert_eq!(added, 0);
+
+    // -r adds everything below the directory, including files with the same name in different directories
+    let walked = db_paths(&walk_paths(&[root.join("docs")], true));
+    let (entries, _) = register_paths(&db, &walked, true).ok().expect("Failed to register the paths recursively.");
+    let mut found = paths(&entries);
+    found.sort();
+    assert_eq!(found, vec!["/docs", "/docs/a.txt", "/docs/sub", "/docs/sub/a.txt"]);
+
+    // a path that can't be added undoes the paths added before it in the same transaction
+    assert!(db.transaction(|| register_paths(&db, &["/new.txt".to_owned(), "/missing.txt".to_owned()], false)).is_err());
+    assert!(db.get_entry("/new.txt").ok().expect("Failed to look up /new.txt.").is_none());
+
+    set_current_dir(cwd).unwrap();
+    remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,147 @@
+use std::cmp::Ordering;
+use std::process::exit;
+use colored::Colorize;
+use crate::cli::args::{check_query_keys, FileEntryExpr, FileSelector, HELP_FLAG, Positional, QUIET_FLAG, Subcommand, EXPLAIN_FLAG, STRICT_KEYS_FLAG, INHERITED_FLAG, SubcommandParseResults};
+use crate::cli::db::{absolute_paths, db_paths, open_database, path_entries};
+use crate::cli::print::{log, print, Logger};
+use crate::cli::query::builtin::{is_builtin, BuiltinKey};
+use crate::cli::query::parse::Query;
+use crate::database::database::{Database, Entry};
+use crate::database::functions::compare_values;
+use crate::database::sqlite::{SqliteDatabase, SqliteError};
+use crate::linq::collectors::IntoVec;
+use crate::linq::group_by::GroupBy;
+
+pub(crate) static SUBCOMMAND: Subcommand = Subcommand {
+    name: "stats",
+    description: "Counts how many files and directories have each value of a key.",
+    positional: Some(Positional {
+        name: "key",
+        count: (Some(1), Some(1)),
+        description: "The key whose values are counted. The entries that don't have it are counted on a line of their own. Without a 'where' or 'from', every entry in the database is counted.",
+    }),
+    file_selector: FileSelector::FILE_LIST | FileSelector::QUERY,
+    flags: vec![HELP_FLAG, QUIET_FLAG, EXPLAIN_FLAG, STRICT_KEYS_FLAG, INHERITED_FLAG],
+    on_parse: |e| run(e),
+};
+
+fn run(res: SubcommandParseResults) {
+    let key = &res.positional()[0];
+    let quiet = res.has_flag(&QUIET_FLAG);
+
+    if is_builtin(key) && BuiltinKey::from_key(key).is_none() {
+        log().error(&format!("There is no built-in key named {}.", key.bold().red()));
+        exit(1);
+    }
+
+    // resolved before open_database moves into the directory containing the database
+    let paths = match res.expr() {
+        Some(FileEntryExpr::List(p)) => Some(absolute_paths(p)),
+        _ => None
+    };
+
+    let db = open_database();
+    check_query_keys(&db, &res);
+    let inherited = res.inherited();
+
+    let counts = match res.expr() {
+        Some(FileEntryExpr::Expr(q)) => db.count_values(q, key),
+        Some(FileEntryExpr::List(_)) => {
+            if is_builtin(key) {
+                log().error(&format!("Built-in keys like {} can only be counted for the entries of a 'where' query.", key.bold().red()));
+                exit(1);
+            }
+
+            count_paths(&db, &db_paths(&paths.unwrap_or_default()), key, inherited, quiet)
+        }
+        None => db.count_values(&Query::everything(inherited), key)
+    };
+
+    let counts = match counts {
+        Ok(c) => c,
+        Err(e) => {
+            log().error(&format!("Failed to count the values of {}: {}", key.bold().yellow(), e));
+            exit(1);
+        }
+    };
+
+    if counts.is_empty() {
+        if !quiet {
+            log().info("There are no entries to count.");
+        }
+        return;
+    }
+
+    let width = counts.iter().map(|x| x.1.to_string().len()).max().unwrap_or(0);
+
+    for (value, count) in counts {
+        let value = match value {
+            Some(v) => v,
+            None => format!("(no {})", key).italic().to_string()
+        };
+
+        print().preformatted(&format!("{:>w$}  {}", count, value, w = width));
+    }
+}
+
+/// Counts the values the entries at the given paths have for a key, grouping them in memory instead of in the database.
+///
+/// An entry given more than once is only counted once, and the paths that aren't in the database are warned about unless `quiet`.
+fn count_paths(db: &SqliteDatabase, paths: &[String], key: &str, inherited: bool, quiet: bool) -> Result<Vec<(Option<String>, usize)>, SqliteError> {
+    let entries = path_entries(db, paths, false, quiet)?;
+
+    let values = if inherited {
+        db.entries_metadata_inherited::<Vec<_>, _>(entries.iter())?
+            .into_iter()
+            .filter_map(|(e, kv)| kv.into_iter().find(|x| x.0 == key).map(|(_, v, _)| (e, v)))
+            .into_vec()
+    }
+    else {
+        db.entries_metadata_get::<Vec<(Entry, String)>, _>(entries.iter(), key)?
+    };
+    let missing = entries.len() - values.len();
+
+    let mut counts = values.into_iter()
+        .group_by(|x: &(Entry, String)| x.1.clone())
+        .into_iter()
+        .map(|(value, entries)| (Some(value), entries.len()))
+        .into_vec();
+
+    if missing > 0 {
+        counts.push((None, missing));
+    }
+
+    sort_counts(&mut counts);
+    Ok(counts)
+}
+
+/// Sorts counts the same way the database does: most common first, then by value, with the entries that have no value last.
+fn sort_counts(counts: &mut [(Option<String>, usize)]) {
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| match (&a.0, &b.0) {
+        (Some(x), Some(y)) => compare_values(x, y),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal
+    }));
+}
+
+#[test]
+fn test_sort_counts() {
+    let mut counts = vec![
+        (Some("todo".to_owned()), 2),
+        (None, 3),
+        (Some("10".to_owned()), 1),
+        (Some("done".to_owned()), 3),
+        (Some("9".to_owned()), 1),
+    ];
+
+    sort_counts(&mut counts);
+
+    assert_eq!(counts, vec![
+        (Some("done".to_owned()), 3),
+        (None, 3),
+        (Some("todo".to_owned()), 2),
+        (Some("9".to_owned()), 1),
+        (Some("10".to_owned()), 1),
+    ]);
+}
@@ -1,15 +1,38 @@
+use std::cmp::max;
 use edit_distance::edit_distance;
 use crate::linq::collectors::IntoVec;
 
-pub fn typos<'a, I: Iterator<Item=&'a str>>(expected: &str, actual: I) -> Vec<(&'a str, usize)> {
-    let mut v = actual.map(|x| (x, edit_distance(expected, x))).into_vec();
-    v.sort_by_key(|x| -x.1);
+/// Returns each of the candidates paired with its edit distance from `actual`, closest first.
+pub fn typos<'a, I: Iterator<Item=&'a str>>(actual: &str, candidates: I) -> Vec<(&'a str, usize)> {
+    let mut v = candidates.map(|x| (x, edit_distance(actual, x))).into_vec();
+    v.sort_by_key(|x| x.1);
     v
 }
 
-pub fn typos_threshold<'a, I: Iterator<Item=&'a str>>(expected: &str, actual: I, threshold: f64, max_count: usize) -> Vec<(&'a str, usize)> {
-    let nchars = f64::round(expected.chars().count() * threshold) as usize;
-    let mut v = typos(expected, actual);
+/// Returns how many edits away from `actual` a string can be while still plausibly being a misspelling of it: `threshold` times its length, but always at least one.
+pub fn max_distance(actual: &str, threshold: f64) -> usize {
+    max(1, f64::round(actual.chars().count() as f64 * threshold) as usize)
+}
+
+/// Returns up to `max_count` of the candidates `actual` is plausibly a misspelling of, closest first.
+///
+/// A candidate qualifies if it's at most `max_distance(actual, threshold)` edits away, and isn't `actual` itself.
+pub fn typos_threshold<'a, I: Iterator<Item=&'a str>>(actual: &str, candidates: I, threshold: f64, max_count: usize) -> Vec<(&'a str, usize)> {
+    let max_distance = max_distance(actual, threshold);
+    let mut v = typos(actual, candidates).into_iter()
+        .filter(|x| x.1 > 0 && x.1 <= max_distance)
+        .into_vec();
     v.truncate(max_count);
-    v.into_iter().filter(|x| x.1 >= nchars).into_vec()
-}
\ No newline at end of file
+    v
+}
+
+#[test]
+fn test_typos_threshold() {
+    let words = ["and", "or", "not", "under", "directly"];
+
+    assert_eq!(typos_threshold("adn", words.iter().copied(), 0.5, 3), vec![("and", 2)]);
+    assert_eq!(typos_threshold("undr", words.iter().copied(), 0.5, 3), vec![("under", 1), ("and", 2)]);
+    assert_eq!(typos_threshold("undr", words.iter().copied(), 0.5, 1), vec![("under", 1)]);
+    assert_eq!(typos_threshold("or", words.iter().copied(), 0.5, 3), vec![]);
+    assert_eq!(typos_threshold("xyzzy", words.iter().copied(), 0.5, 3), vec![]);
+}
@@ -1,6 +1,7 @@
 use std::iter::FromIterator;
 
-use crate::database::models::{Directory, File};
+use crate::cli::query::parse::Query;
+use crate::database::models::{Directory, File, SavedQuery};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Entry {
@@ -24,15 +25,27 @@ impl Entry {
     }
 }
 
+pub struct QueryExplanation {
+    /// The statement a query compiles to, along with the values bound to it.
+    pub sql: String,
+    /// The steps the database will take to run the statement, indented by how deeply they're nested.
+    pub plan: Vec<String>,
+}
+
 pub trait Database<'a, E> {
+    /// Runs `f` in a transaction, so either every change it makes is kept or, if it returns an error, none of them are.
+    fn transaction<T, F: FnOnce() -> Result<T, E>>(&self, f: F) -> Result<T, E>;
+
     fn file_directory(&self, f: &File) -> Result<Directory, E>;
 
     fn entry_metadata<B: FromIterator<(String, String)>>(&self, entry: &Entry) -> Result<B, E>;
     fn entry_metadata_get(&self, entry: &Entry, key: &str) -> Result<Option<String>, E>;
     fn entry_metadata_set(&self, entry: &Entry, key: &str, value: Option<&str>) -> Result<Option<String>, E>;
     fn entry_metadata_clear(&self, entry: &Entry) -> Result<usize, E>;
+    fn entry_metadata_inherited<B: FromIterator<(String, String, Option<Directory>)>>(&self, entry: &Entry) -> Result<B, E>;
 
     fn entries_metadata<'b, B: FromIterator<(Entry, Vec<(String, String)>)>, I: Iterator<Item=&'b Entry>>(&self, entries: I) -> Result<B, E>;
+    fn entries_metadata_inherited<'b, B: FromIterator<(Entry, Vec<(String, String, Option<Directory>)>)>, I: Iterator<Item=&'b Entry>>(&self, entries: I) -> Result<B, E>;
     fn entries_metadata_get<'b, B: FromIterator<(Entry, String)>, I: Iterator<Item=&'b Entry>>(&self, entries: I, key: &str) -> Result<B, E>;
     fn entries_metadata_set<'b, B: FromIterator<(Entry, Option<String>)>, I: Iterator<Item=&'b Entry>>(&self, entries: I, key: &str, value: Option<&str>) -> Result<B, E>;
     fn entries_metadata_clear<'b, I: Iterator<Item=&'b Entry>>(&self, entries: I) -> Result<usize, E>;
@@ -42,8 +55,14 @@ pub trait Database<'a, E> {
     fn directory_entries_with_key<'b, B: FromIterator<Entry>>(&self, d: &Directory, key: &str) -> Result<B, E>;
     fn directory_entries_with_key_and_value<'b, B: FromIterator<Entry>>(&self, d: &Directory, key: &str, value: &str) -> Result<B, E>;
 
+    fn query_entries<B: FromIterator<Entry>>(&self, query: &Query) -> Result<B, E>;
+    fn explain_query(&self, query: &Query) -> Result<QueryExplanation, E>;
+    fn count_values<B: FromIterator<(Option<String>, usize)>>(&self, query: &Query, key: &str) -> Result<B, E>;
+    fn metadata_keys<B: FromIterator<String>>(&self) -> Result<B, E>;
+
     fn get_entry(&self, path: &str) -> Result<Option<Entry>, E>;
     fn get_entries<'b, B: FromIterator<Entry>, I: Iterator<Item=&'b str>>(&self, paths: I) -> Result<B, E>;
+    fn entry_path(&self, entry: &Entry) -> Result<String, E>;
 
     fn add_directory(&self, path: &str) -> Result<(Directory, bool), E>;
     fn add_directories<'b, I: Iterator<Item=&'b str>>(&self, paths: I) -> Result<usize, E>;
@@ -52,4 +71,9 @@ pub trait Database<'a, E> {
 
     fn remove_entry(&self, entry: &Entry) -> Result<bool, E>;
     fn remove_entries<'b, I: Iterator<Item=&'b Entry>>(&self, entries: I) -> Result<usize, E>;
+
+    fn saved_query(&self, name: &str) -> Result<Option<SavedQuery>, E>;
+    fn saved_queries<B: FromIterator<SavedQuery>>(&self) -> Result<B, E>;
+    fn save_query(&self, name: &str, query: &str) -> Result<bool, E>;
+    fn delete_saved_query(&self, name: &str) -> Result<bool, E>;
 }
@@ -0,0 +1,176 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use diesel::QueryResult;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::{Bool, Double, Integer, Nullable, Text};
+use diesel::sqlite::{Sqlite, SqliteConnection};
+use edit_distance::edit_distance;
+use fancy_regex::{Error, Regex, RegexBuilder};
+
+use crate::filesystem::fs::entry_metadata;
+
+use super::path::Path;
+
+/*
+    functions implemented in Rust that the compiled queries in database::query call into
+    every one of them has to be registered on a connection by `register` before it can be used
+*/
+
+sql_function! {
+    /// Returns -1, 0, or 1 depending on whether the first value orders before, the same as, or after the second.
+    fn meta_compare(a: Text, b: Text) -> Integer;
+}
+
+sql_function! {
+    /// Returns the value as a number if it looks like one, or NULL if it doesn't (or is NULL itself).
+    fn meta_number(value: Nullable<Text>) -> Nullable<Double>;
+}
+
+sql_function! {
+    /// Returns how many single-character insertions, deletions or substitutions it takes to turn one value into the other.
+    fn meta_distance(a: Text, b: Text) -> Integer;
+}
+
+sql_function! {
+    /// Returns true if the regex matches anywhere within the value.
+    fn meta_matches(value: Text, pattern: Text, ignore_case: Bool) -> Bool;
+}
+
+sql_function! {
+    /// Returns the last component of a directory path.
+    fn meta_filename(path: Text) -> Text;
+}
+
+sql_function! {
+    /// Returns the extension of a file or directory name, or NULL if it has none.
+    fn meta_extension(name: Text) -> Nullable<Text>;
+}
+
+sql_function! {
+    /// Returns the size in bytes of the file at a database path, or NULL if it isn't a file that can be read.
+    fn meta_size(path: Text) -> Nullable<Text>;
+}
+
+sql_function! {
+    /// Returns the last modification time in seconds since the Unix epoch of the entry at a database path, or NULL if it can't be read.
+    fn meta_mtime(path: Text) -> Nullable<Text>;
+}
+
+/// How many times a user-supplied regex may backtrack on a single value before it is abandoned.
+const REGEX_BACKTRACK_LIMIT: usize = 100_000;
+/// The approximate maximum size in bytes of a compiled user-supplied regex.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// What meta_matches returns: whether the regex matched, or the pattern that hit REGEX_BACKTRACK_LIMIT.
+/// A registered function can only fail by failing to convert its result, so that's how the pattern fails the query it's in.
+#[derive(Debug)]
+struct RegexMatch(Result<bool, String>);
+
+impl ToSql<Bool, Sqlite> for RegexMatch {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        match &self.0 {
+            Ok(b) => ToSql::<Bool, Sqlite>::to_sql(b, out),
+            Err(pattern) => Err(format!("The pattern '{}' backtracked too many times while being matched. Try rewriting it without nested repetition (e.g. '(a+)+').", pattern).into())
+        }
+    }
+}
+
+/// Returns the value of a metadata value that looks like a number, or None if it doesn't look like one.
+pub fn parse_number(s: &str) -> Option<f64> {
+    match s.trim().parse::<f64>() {
+        Ok(n) if n.is_finite() => Some(n),
+        _ => None
+    }
+}
+
+/// Orders two metadata values: values that look like numbers come before the ones that don't and are ordered numerically, and the rest are ordered lexicographically.
+pub fn compare_values(a: &str, b: &str) -> Ordering {
+    match (parse_number(a), parse_number(b)) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b)
+    }
+}
+
+/// Compiles a user-supplied regex with the limits that keep it from running away.
+pub fn build_regex(pattern: &str, ignore_case: bool) -> Result<Regex, Error> {
+    let pattern = if ignore_case {
+        "(?i)".to_owned() + pattern
+    } else {
+        pattern.to_owned()
+    };
+
+    RegexBuilder::new(&pattern)
+        .backtrack_limit(REGEX_BACKTRACK_LIMIT)
+        .delegate_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+pub fn register(conn: &SqliteConnection) -> QueryResult<()> {
+    meta_compare::register_impl(conn, |a: String, b: String| compare_values(&a, &b) as i32)?;
+
+    meta_distance::register_impl(conn, |a: String, b: String| edit_distance(&a, &b) as i32)?;
+
+    meta_number::register_impl(conn, |value: Option<String>| value.and_then(|v| parse_number(&v)))?;
+
+    meta_filename::register_impl(conn, |path: String| Path::new(&path).filename().to_owned())?;
+
+    meta_extension::register_impl(conn, |name: String| {
+        std::path::Path::new(&name).extension()
+            .and_then(|x| x.to_str())
+            .map(|x| x.to_owned())
+    })?;
+
+    // these depend on the filesystem, so sqlite cannot assume that they return the same result for the same input
+    meta_size::register_nondeterministic_impl(conn, |path: String| {
+        entry_metadata(&path).ok()
+            .filter(|m| m.is_file())
+            .map(|m| m.len().to_string())
+    })?;
+
+    meta_mtime::register_nondeterministic_impl(conn, |path: String| {
+        entry_metadata(&path).ok()
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs().to_string())
+    })?;
+
+    let cache = Mutex::new(HashMap::<(String, bool), Option<Regex>>::new());
+    meta_matches::register_impl(conn, move |value: String, pattern: String, ignore_case: bool| {
+        let mut cache = cache.lock().expect("Regex cache lock is poisoned. This should never happen.");
+
+        let re = cache.entry((pattern.clone(), ignore_case))
+            .or_insert_with(|| build_regex(&pattern, ignore_case).ok());
+
+        RegexMatch(match re.as_ref().map(|r| r.is_match(&value)) {
+            Some(Ok(b)) => Ok(b),
+            Some(Err(_)) => Err(pattern),
+            None => Ok(false)
+        })
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_compare_values() {
+    let cases = [
+        ("4", "10", Ordering::Less),
+        ("4.0", "4", Ordering::Equal),
+        ("-1", "0.5", Ordering::Less),
+        ("4", "10a", Ordering::Less),
+        ("10", "10a", Ordering::Less),
+        ("10a", "4", Ordering::Greater),
+        ("abc", "abd", Ordering::Less),
+        ("inf", "1", Ordering::Greater)
+    ];
+
+    for (a, b, exp) in cases.iter() {
+        assert_eq!(compare_values(a, b), *exp);
+    }
+}
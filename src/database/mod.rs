@@ -1,6 +1,8 @@
 pub mod database;
+pub mod functions;
 pub mod models;
 pub mod option_result;
 pub mod path;
+pub mod query;
 pub mod schema;
 pub mod sqlite;
\ No newline at end of file
@@ -76,4 +76,19 @@ pub struct NewDirectoryKeyValuePair<'a> {
     pub directory_id: i32,
     pub key: &'a str,
     pub value: &'a str,
+}
+
+#[derive(Identifiable, Queryable, PartialEq, Eq, Debug, Clone)]
+#[table_name = "SavedQueries"]
+pub struct SavedQuery {
+    pub id: i32,
+    pub name: String,
+    pub query: String,
+}
+
+#[derive(Insertable, PartialEq, Eq, Debug)]
+#[table_name = "SavedQueries"]
+pub struct NewSavedQuery<'a> {
+    pub name: &'a str,
+    pub query: &'a str,
 }
\ No newline at end of file
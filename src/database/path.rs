@@ -26,7 +26,7 @@ impl Path {
     }
 
     pub fn filename(&self) -> &str {
-        &self.pat[self.pat.trim_end_matches(|c| c != '/').trim_end_matches('/').len()..]
+        &self.pat[self.pat.trim_end_matches(|c| c != '/').len()..]
     }
 
     pub fn parent_str(s: &str) -> &str {
@@ -0,0 +1,1218 @@
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+use diesel::query_builder::{AstPass, Query, QueryFragment, QueryId};
+use diesel::sql_types::{BigInt, Binary, Integer, Nullable, Text};
+use diesel::sqlite::{Sqlite, SqliteConnection};
+
+use crate::cli::query::builtin::BuiltinKey;
+use crate::cli::query::lexeme::EqualityKind;
+use crate::cli::query::parse::{self, parse_text, AndQuery, Factor, OrQuery, Quantifier, Scope};
+use crate::cli::typo::max_distance;
+use crate::linq::collectors::IntoVec;
+
+use super::database::Entry;
+use super::functions::build_regex;
+use super::models::{Directory, File};
+use super::sqlite::SqliteError;
+use super::sqlite::SqliteError::ApplicationError;
+
+/*
+    a query is compiled into a single statement of the form
+
+        SELECT 1, Directories.id, NULL, Directories.path, NULL FROM Directories WHERE <query>
+        UNION ALL
+        SELECT 0, Files.id, Files.directory_id, Files.filename, Files.hash FROM Files WHERE <query>
+
+    where every factor of <query> becomes a correlated subquery against that table's metadata table,
+    or an expression over the row itself for built-in keys like @name
+
+    if the query has an 'order by', 'limit' or 'offset', the union is wrapped so it can be sorted and paged
+
+        SELECT kind, id, directory_id, name, hash FROM (
+            SELECT 1 AS kind, ..., Directories.path AS path, <value of key 0> AS value_0, ... FROM Directories WHERE <query>
+            UNION ALL
+            SELECT 0 AS kind, ..., <path of the file> AS path, <value of key 0> AS value_0, ... FROM Files WHERE <query>
+        ) ORDER BY <value_0>, ..., path LIMIT <limit> OFFSET <offset>
+
+    entries that don't have a key sort after the ones that do, and values that look like numbers sort
+    numerically before the ones that don't, the same way meta_compare orders them.
+    a query with a 'near' and no 'order by' is sorted on the entries' distances for it the same way
+
+    counting the values of a key selects that key as one more value_n column of the same union and groups by it
+
+        SELECT value_n, COUNT(*) FROM (...) GROUP BY value_n ORDER BY COUNT(*) DESC, <value_n>
+
+    'parent.' and 'ancestor.' select the directories satisfying their factor against their own metadata in a subquery of their own,
+    and look for the directory the entry is in, or for the paths above the entry, among them
+
+    with inheritance, every subquery against a metadata table is made against a derived table of the same shape instead,
+    which adds the metadata of the directories above each entry for the keys it doesn't have itself, taking each key from the nearest directory that has it
+*/
+
+/// The fraction of a value's length that a 'near' without a 'within' allows for edits.
+const NEAR_THRESHOLD: f64 = 0.25;
+
+const FILE_KIND: i32 = 0;
+const DIRECTORY_KIND: i32 = 1;
+
+/// (kind, id, directory_id, filename/path, hash)
+pub type EntryRow = (i32, i32, Option<i32>, String, Option<Vec<u8>>);
+
+struct EntryTable {
+    kind: i32,
+    table: &'static str,
+    metadata: &'static str,
+    foreign_key: &'static str,
+    // the SQL for the (id, directory_id, filename/path, hash) of an EntryRow
+    columns: [&'static str; 4],
+    // the SQL for the built-in attributes of an entry in this table
+    path: &'static str,
+    name: &'static str,
+    hash: &'static str,
+    type_name: &'static str,
+    // the SQL for a table like `metadata` that also has the metadata each entry inherits
+    inherited: &'static str,
+    // the SQL for a condition that the directory `s` is the one the entry is in
+    in_parent: &'static str,
+    // the SQL for a SELECT of the path of the directory the entry is in, which is empty for the root directory
+    parent_path: &'static str,
+}
+
+// the SQL for Path::parent_str of a path: everything before its last '/', or '/' if that's nothing
+macro_rules! parent_str {
+    ($p:literal) => {
+        concat!("coalesce(nullif(rtrim(rtrim(", $p, ", replace(", $p, ", '/', '')), '/'), ''), '/')")
+    }
+}
+
+// a directory is an ancestor of itself here, so that a file inherits from the directory it's in
+macro_rules! ancestor_or_self {
+    ($a:literal, $d:literal) => {
+        concat!("(", $a, ".path = ", $d, ".path OR substr(", $d, ".path, 1, length(rtrim(", $a, ".path, '/')) + 1) = rtrim(", $a, ".path, '/') || '/')")
+    }
+}
+
+// the metadata of the directories above `d`, leaving out any key a nearer one of them also has
+macro_rules! ancestor_metadata {
+    ($d:literal) => {
+        concat!(
+            "Directories a INNER JOIN DirectoryMetadata dm ON dm.directory_id = a.id WHERE ", ancestor_or_self!("a", $d),
+            " AND NOT EXISTS (SELECT 1 FROM Directories b INNER JOIN DirectoryMetadata n ON n.directory_id = b.id WHERE n.key = dm.key AND ",
+            ancestor_or_self!("b", $d), " AND length(b.path) > length(a.path))"
+        )
+    }
+}
+
+static FILES: EntryTable = EntryTable {
+    kind: FILE_KIND,
+    table: "Files",
+    metadata: "FileMetadata",
+    foreign_key: "file_id",
+    columns: ["Files.id", "Files.directory_id", "Files.filename", "Files.hash"],
+    // rtrim() keeps files in the root directory from getting a double slash
+    path: "(rtrim((SELECT d.path FROM Directories d WHERE d.id = Files.directory_id), '/') || '/' || Files.filename)",
+    name: "Files.filename",
+    hash: "lower(hex(Files.hash))",
+    type_name: "file",
+    inherited: concat!(
+        "(SELECT file_id, key, value FROM FileMetadata UNION ALL ",
+        "SELECT f.id AS file_id, dm.key AS key, dm.value AS value FROM Files f INNER JOIN Directories fd ON fd.id = f.directory_id, ", ancestor_metadata!("fd"),
+        " AND NOT EXISTS (SELECT 1 FROM FileMetadata o WHERE o.file_id = f.id AND o.key = dm.key))"
+    ),
+    in_parent: "s.id = Files.directory_id",
+    parent_path: "SELECT d.path FROM Directories d WHERE d.id = Files.directory_id",
+};
+
+static DIRECTORIES: EntryTable = EntryTable {
+    kind: DIRECTORY_KIND,
+    table: "Directories",
+    metadata: "DirectoryMetadata",
+    foreign_key: "directory_id",
+    columns: ["Directories.id", "NULL", "Directories.path", "NULL"],
+    path: "Directories.path",
+    name: "meta_filename(Directories.path)",
+    hash: "NULL",
+    type_name: "dir",
+    inherited: concat!("(SELECT d.id AS directory_id, dm.key AS key, dm.value AS value FROM Directories d, ", ancestor_metadata!("d"), ")"),
+    in_parent: concat!("Directories.path <> '/' AND s.path = ", parent_str!("Directories.path")),
+    parent_path: concat!("SELECT ", parent_str!("Directories.path"), " WHERE Directories.path <> '/'"),
+};
+
+enum Fragment {
+    Sql(String),
+    Text(String),
+}
+
+pub struct EntryQuery {
+    fragments: Vec<Fragment>,
+    inherited: bool,
+}
+
+impl EntryQuery {
+    fn new(inherited: bool) -> Self {
+        EntryQuery { fragments: Vec::new(), inherited }
+    }
+
+    /// Returns the SQL for the table holding the metadata of the entries in the given table.
+    fn metadata(&self, t: &EntryTable) -> &'static str {
+        if self.inherited { t.inherited } else { t.metadata }
+    }
+
+    fn sql(&mut self, s: &str) {
+        self.fragments.push(Fragment::Sql(s.to_owned()));
+    }
+
+    fn text(&mut self, s: &str) {
+        self.fragments.push(Fragment::Text(s.to_owned()));
+    }
+
+    /// Emits the SELECT of every entry in the given table that satisfies the filter.
+    ///
+    /// If `columns` is given, the columns are named and followed by the entry's path and a value_n column for each of them.
+    fn select(&mut self, filter: &OrQuery, t: &EntryTable, columns: Option<&[Column]>) -> Result<(), SqliteError> {
+        let [id, directory_id, name, hash] = t.columns;
+
+        match columns {
+            None => self.sql(&format!("SELECT {}, {}, {}, {}, {}", t.kind, id, directory_id, name, hash)),
+            Some(columns) => {
+                self.sql(&format!("SELECT {} AS kind, {} AS id, {} AS directory_id, {} AS name, {} AS hash, {} AS path",
+                                  t.kind, id, directory_id, name, hash, t.path));
+
+                for (i, c) in columns.iter().enumerate() {
+                    self.sql(", ");
+                    self.column(t, c);
+                    self.sql(&format!(" AS value_{}", i));
+                }
+            }
+        }
+
+        self.sql(&format!(" FROM {} WHERE ", t.table));
+        self.or_query(filter, t)
+    }
+
+    /// Emits the parenthesized union of the directories and files that satisfy the filter, with a value_n column for each of the given columns.
+    fn union(&mut self, filter: &OrQuery, columns: &[Column]) -> Result<(), SqliteError> {
+        self.sql("(");
+        self.select(filter, &DIRECTORIES, Some(columns))?;
+        self.sql(" UNION ALL ");
+        self.select(filter, &FILES, Some(columns))?;
+        self.sql(")");
+        Ok(())
+    }
+
+    /// Emits the ORDER BY, LIMIT and OFFSET of a query over the union, whose first value columns are the `sort_columns` of the query.
+    fn order_and_page(&mut self, query: &parse::Query) {
+        self.sql(" ORDER BY ");
+
+        for (i, (_, descending)) in sort_columns(query).iter().enumerate() {
+            self.sql(&value_order(&format!("value_{}", i), *descending));
+            self.sql(", ");
+        }
+        // so entries that tie on every key still come out the same way every time
+        self.sql("path");
+
+        match (query.limit, query.offset) {
+            (Some(l), Some(o)) => self.sql(&format!(" LIMIT {} OFFSET {}", l, o)),
+            (Some(l), None) => self.sql(&format!(" LIMIT {}", l)),
+            // sqlite only takes an OFFSET after a LIMIT, and a negative LIMIT means no limit at all
+            (None, Some(o)) => self.sql(&format!(" LIMIT -1 OFFSET {}", o)),
+            (None, None) => {}
+        }
+    }
+
+    /// Emits the value of a column for an entry, which is NULL if the entry doesn't have the key it's for.
+    fn column(&mut self, t: &EntryTable, c: &Column) {
+        match c {
+            Column::Key(key) => match BuiltinKey::from_key(key) {
+                Some(b) => self.sql(&builtin_value(t, b)),
+                None => {
+                    self.sql(&format!("(SELECT m.value FROM {} m WHERE m.{} = {}.id AND m.key = ", self.metadata(t), t.foreign_key, t.table));
+                    self.text(key);
+                    self.sql(")");
+                }
+            },
+            Column::Distance(key, value) => {
+                self.sql("(SELECT MIN(meta_distance(m.value, ");
+                self.text(value);
+                self.sql(&format!(")) FROM {} m WHERE m.{} = {}.id AND m.key = ", self.metadata(t), t.foreign_key, t.table));
+                self.text(key);
+                self.sql(")");
+            }
+        }
+    }
+
+    fn or_query(&mut self, q: &OrQuery, t: &EntryTable) -> Result<(), SqliteError> {
+        self.and_query(&q.and_query, t)?;
+
+        if let Some(next) = &q.next {
+            self.sql(" OR ");
+            self.or_query(next, t)?;
+        }
+
+        Ok(())
+    }
+
+    fn and_query(&mut self, q: &AndQuery, t: &EntryTable) -> Result<(), SqliteError> {
+        self.factor(&q.factor, t)?;
+
+        if let Some(next) = &q.next {
+            self.sql(" AND ");
+            self.and_query(next, t)?;
+        }
+
+        Ok(())
+    }
+
+    fn factor(&mut self, f: &Factor, t: &EntryTable) -> Result<(), SqliteError> {
+        match f {
+            Factor::Query(q) => {
+                self.sql("(");
+                self.or_query(q, t)?;
+                self.sql(")");
+                Ok(())
+            }
+            Factor::Not(f) => {
+                self.sql("NOT (");
+                self.factor(f, t)?;
+                self.sql(")");
+                Ok(())
+            }
+            Factor::Key(k) | Factor::KeyEqualsValue((k, _, _)) | Factor::KeyIn((k, _)) | Factor::KeyNear((k, _, _)) => self.key_value(t, k, |q, value| q.condition(f, value)),
+            Factor::KeyEqualsKey((k, kind, other)) => self.key_comparison(t, k, *kind, other),
+            Factor::Quantified((quantifier, inner)) => match &**inner {
+                Factor::Key(k) | Factor::KeyEqualsValue((k, _, _)) | Factor::KeyIn((k, _)) | Factor::KeyNear((k, _, _)) => self.key_pattern(t, *quantifier, k, |q, value| q.condition(inner, value)),
+                _ => Err(ApplicationError(format!("'{}' can only be applied to a key, not '{}'.", quantifier, inner)))
+            },
+            Factor::Scoped((scope, inner)) => self.scoped(t, *scope, inner),
+            Factor::Under(p) => {
+                self.sql(&format!("{} GLOB ", t.path));
+                self.text(&(glob_escape(directory_prefix(p)) + "/?*"));
+                Ok(())
+            }
+            Factor::DirectlyIn(p) => {
+                let prefix = glob_escape(directory_prefix(p));
+
+                self.sql(&format!("({} GLOB ", t.path));
+                self.text(&(prefix.clone() + "/?*"));
+                self.sql(&format!(" AND {} NOT GLOB ", t.path));
+                self.text(&(prefix + "/*/*"));
+                self.sql(")");
+                Ok(())
+            }
+            Factor::SavedQuery(name) => Err(ApplicationError(format!("The saved query '{}' has to be expanded before the query is compiled.", name)))
+        }
+    }
+
+    /// Appends the restrictions a key factor puts on the value of its key, each starting with " AND ".
+    fn condition(&mut self, f: &Factor, value: &str) -> Result<(), SqliteError> {
+        match f {
+            Factor::KeyEqualsValue((k, kind, v)) => self.comparison(value, *kind, k, v),
+            Factor::KeyIn((_, vs)) => {
+                if vs.is_empty() {
+                    self.sql(" AND 0");
+                    return Ok(());
+                }
+
+                self.sql(&format!(" AND {} IN (", value));
+                for (i, v) in vs.iter().enumerate() {
+                    if i > 0 {
+                        self.sql(", ");
+                    }
+                    self.text(v);
+                }
+                self.sql(")");
+                Ok(())
+            }
+            Factor::KeyNear((_, v, within)) => {
+                let max = within.unwrap_or_else(|| max_distance(v, NEAR_THRESHOLD));
+
+                self.sql(&format!(" AND meta_distance({}, ", value));
+                self.text(v);
+                self.sql(&format!(") <= {}", max));
+                Ok(())
+            }
+            _ => Ok(())
+        }
+    }
+
+    /// Appends " AND <value> <kind> <v>".
+    fn comparison(&mut self, value: &str, kind: EqualityKind, k: &str, v: &str) -> Result<(), SqliteError> {
+        match kind {
+            EqualityKind::Strict => {
+                self.sql(&format!(" AND {} = ", value));
+                self.text(v);
+            }
+            EqualityKind::NotEqual => {
+                self.sql(&format!(" AND {} <> ", value));
+                self.text(v);
+            }
+            EqualityKind::Less | EqualityKind::LessEqual | EqualityKind::Greater | EqualityKind::GreaterEqual => {
+                self.sql(&format!(" AND meta_compare({}, ", value));
+                self.text(v);
+                self.sql(&format!(") {} 0", comparison_operator(kind)));
+            }
+            EqualityKind::Matches | EqualityKind::MatchesIgnoreCase => {
+                let ignore_case = kind == EqualityKind::MatchesIgnoreCase;
+
+                if let Err(e) = build_regex(v, ignore_case) {
+                    return Err(ApplicationError(format!("The pattern '{}' given for '{}' is not a valid regex: {}", v, k, e)));
+                }
+
+                self.sql(&format!(" AND meta_matches({}, ", value));
+                self.text(v);
+                self.sql(if ignore_case { ", 1)" } else { ", 0)" });
+            }
+            EqualityKind::Like => {
+                self.sql(&format!(" AND {} LIKE ", value));
+                self.text(&like_pattern(v));
+                self.sql(" ESCAPE '\\'");
+            }
+            EqualityKind::Glob => {
+                self.sql(&format!(" AND {} GLOB ", value));
+                self.text(&glob_pattern(v));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends " AND <left> <kind> <right>" for two SQL expressions.
+    fn value_comparison(&mut self, left: &str, kind: EqualityKind, right: &str) -> Result<(), SqliteError> {
+        match kind {
+            EqualityKind::Strict => self.sql(&format!(" AND {} = {}", left, right)),
+            EqualityKind::NotEqual => self.sql(&format!(" AND {} <> {}", left, right)),
+            EqualityKind::Less | EqualityKind::LessEqual | EqualityKind::Greater | EqualityKind::GreaterEqual => {
+                self.sql(&format!(" AND meta_compare({}, {}) {} 0", left, right, comparison_operator(kind)))
+            }
+            _ => return Err(ApplicationError(format!("'{}' can't compare the values of two keys.", kind.symbol())))
+        }
+
+        Ok(())
+    }
+
+    /// Emits a condition that is true if the entry has values for both keys and they compare the given way.
+    fn key_comparison(&mut self, t: &EntryTable, key: &str, kind: EqualityKind, other: &str) -> Result<(), SqliteError> {
+        match (BuiltinKey::from_key(key), BuiltinKey::from_key(other)) {
+            (None, None) => {
+                // joins the entry's metadata with itself to pair its value for `key` with its value for `other`
+                self.sql(&format!("EXISTS (SELECT 1 FROM {0} m INNER JOIN {0} r ON r.{1} = m.{1} WHERE m.{1} = {2}.id AND m.key = ", self.metadata(t), t.foreign_key, t.table));
+                self.text(key);
+                self.sql(" AND r.key = ");
+                self.text(other);
+                self.value_comparison("m.value", kind, "r.value")?;
+                self.sql(")");
+                Ok(())
+            }
+            (_, Some(b)) => {
+                let right = builtin_value(t, b);
+
+                self.key_value(t, key, |q, left| {
+                    q.sql(&format!(" AND {} IS NOT NULL", right));
+                    q.value_comparison(left, kind, &right)
+                })
+            }
+            (Some(b), None) => {
+                let left = builtin_value(t, b);
+
+                self.key_value(t, other, |q, right| {
+                    q.sql(&format!(" AND {} IS NOT NULL", left));
+                    q.value_comparison(&left, kind, right)
+                })
+            }
+        }
+    }
+
+    /// Emits a condition that is true if the entry has a value for `key` satisfying `cond`.
+    ///
+    /// `cond` is given the SQL for the value and appends any restrictions on it, each starting with " AND ".
+    fn key_value<F: FnOnce(&mut Self, &str) -> Result<(), SqliteError>>(&mut self, t: &EntryTable, key: &str, cond: F) -> Result<(), SqliteError> {
+        match BuiltinKey::from_key(key) {
+            Some(b) => {
+                let value = builtin_value(t, b);
+
+                self.sql(&format!("({} IS NOT NULL", value));
+                cond(self, &value)?;
+                self.sql(")");
+            }
+            None => {
+                self.sql(&format!("EXISTS (SELECT 1 FROM {} m WHERE m.{} = {}.id AND m.key = ", self.metadata(t), t.foreign_key, t.table));
+                self.text(key);
+                cond(self, "m.value")?;
+                self.sql(")");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emits a condition that is true if the directory the entry is in, or any directory above it, satisfies the factor.
+    fn scoped(&mut self, t: &EntryTable, scope: Scope, f: &Factor) -> Result<(), SqliteError> {
+        // Directories is named again inside the subquery, so the factor is about the directory even when the entry is a directory too
+        self.sql("EXISTS (SELECT 1 FROM (SELECT Directories.id AS id, Directories.path AS path FROM Directories WHERE ");
+
+        // the directory's own metadata, even with inheritance
+        let inherited = std::mem::replace(&mut self.inherited, false);
+        let res = self.factor(f, &DIRECTORIES);
+        self.inherited = inherited;
+        res?;
+
+        match scope {
+            Scope::Parent => self.sql(&format!(") s WHERE {})", t.in_parent)),
+            // the entry's ancestors are listed by walking up from its directory, so each one is looked up by its path
+            Scope::Ancestor => self.sql(&format!(
+                ") s WHERE s.path IN (WITH RECURSIVE a(path) AS ({} UNION SELECT {} FROM a WHERE a.path <> '/') SELECT path FROM a))",
+                t.parent_path,
+                parent_str!("a.path")
+            ))
+        }
+
+        Ok(())
+    }
+
+    /// Emits a condition that is true if any or all of the entry's keys matching `pattern` have a value satisfying `cond`.
+    ///
+    /// For 'all', the entry also has to have at least one key matching the pattern.
+    fn key_pattern<F: FnOnce(&mut Self, &str) -> Result<(), SqliteError>>(&mut self, t: &EntryTable, quantifier: Quantifier, pattern: &str, cond: F) -> Result<(), SqliteError> {
+        // keys can't contain GLOB's other special characters, so the pattern can be used as-is
+        let matching = format!("SELECT 1 FROM {} m WHERE m.{} = {}.id AND m.key GLOB ", self.metadata(t), t.foreign_key, t.table);
+
+        match quantifier {
+            Quantifier::Any => {
+                self.sql(&format!("EXISTS ({}", matching));
+                self.text(pattern);
+                cond(self, "m.value")?;
+                self.sql(")");
+            }
+            Quantifier::All => {
+                self.sql(&format!("(EXISTS ({}", matching));
+                self.text(pattern);
+                self.sql(&format!(") AND NOT EXISTS ({}", matching));
+                self.text(pattern);
+                // the restrictions each start with " AND ", so they're negated together after a leading 1
+                self.sql(" AND NOT (1");
+                cond(self, "m.value")?;
+                self.sql(")))");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the ORDER BY terms that sort a column of values the way meta_compare orders them, with NULLs last.
+fn value_order(column: &str, descending: bool) -> String {
+    let dir = if descending { " DESC" } else { "" };
+    format!("{0} IS NULL, meta_number({0}) IS NULL, meta_number({0}){1}, {0}{1}", column, dir)
+}
+
+/// Returns the SQL for the value of a built-in key of an entry in the given table.
+fn builtin_value(t: &EntryTable, key: BuiltinKey) -> String {
+    match key {
+        BuiltinKey::Name => t.name.to_owned(),
+        BuiltinKey::Path => t.path.to_owned(),
+        BuiltinKey::Ext => format!("meta_extension({})", t.name),
+        BuiltinKey::Size => format!("meta_size({})", t.path),
+        BuiltinKey::Mtime => format!("meta_mtime({})", t.path),
+        BuiltinKey::Type => format!("'{}'", t.type_name),
+        BuiltinKey::Hash => t.hash.to_owned()
+    }
+}
+
+/// Translates a shell pattern into a pattern for SQLite's LIKE that uses a backslash as its escape character.
+pub fn like_pattern(pattern: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => ret.push('%'),
+            '?' => ret.push('_'),
+            '%' | '_' => {
+                ret.push('\\');
+                ret.push(c);
+            }
+            '\\' => {
+                let escaped = chars.next().unwrap_or('\\');
+                if escaped == '%' || escaped == '_' || escaped == '\\' {
+                    ret.push('\\');
+                }
+                ret.push(escaped);
+            }
+            _ => ret.push(c)
+        }
+    }
+
+    ret
+}
+
+/// Translates a shell pattern into a pattern for SQLite's GLOB, which has no escape character of its own.
+pub fn glob_pattern(pattern: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars.next().unwrap_or('\\');
+                if escaped == '*' || escaped == '?' || escaped == '[' {
+                    ret.push('[');
+                    ret.push(escaped);
+                    ret.push(']');
+                } else {
+                    ret.push(escaped);
+                }
+            }
+            _ => ret.push(c)
+        }
+    }
+
+    ret
+}
+
+/// Escapes the characters in a string that SQLite's GLOB would otherwise treat as wildcards.
+pub fn glob_escape(s: &str) -> String {
+    let mut ret = String::new();
+
+    for c in s.chars() {
+        if c == '*' || c == '?' || c == '[' {
+            ret.push('[');
+            ret.push(c);
+            ret.push(']');
+        } else {
+            ret.push(c);
+        }
+    }
+
+    ret
+}
+
+/// Returns what the paths of entries below a directory start with before their next '/'.
+fn directory_prefix(path: &str) -> &str {
+    if path == "/" { "" } else { path }
+}
+
+fn comparison_operator(kind: EqualityKind) -> &'static str {
+    match kind {
+        EqualityKind::Less => "<",
+        EqualityKind::LessEqual => "<=",
+        EqualityKind::Greater => ">",
+        EqualityKind::GreaterEqual => ">=",
+        _ => unreachable!("{:?} is not an ordering comparison.", kind)
+    }
+}
+
+impl QueryFragment<Sqlite> for EntryQuery {
+    fn walk_ast(&self, mut out: AstPass<Sqlite>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        for fragment in &self.fragments {
+            match fragment {
+                Fragment::Sql(s) => out.push_sql(s),
+                Fragment::Text(s) => out.push_bind_param::<Text, _>(s)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl QueryId for EntryQuery {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl Query for EntryQuery {
+    type SqlType = (Integer, Integer, Nullable<Integer>, Text, Nullable<Binary>);
+}
+
+impl RunQueryDsl<SqliteConnection> for EntryQuery {}
+
+/// (value, count)
+pub type CountRow = (Option<String>, i64);
+
+/// A compiled query that counts the values of a key instead of selecting entries.
+pub struct CountQuery(EntryQuery);
+
+impl QueryFragment<Sqlite> for CountQuery {
+    fn walk_ast(&self, out: AstPass<Sqlite>) -> QueryResult<()> {
+        self.0.walk_ast(out)
+    }
+}
+
+impl QueryId for CountQuery {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl Query for CountQuery {
+    type SqlType = (Nullable<Text>, BigInt);
+}
+
+impl RunQueryDsl<SqliteConnection> for CountQuery {}
+
+/// (id, parent, unused, detail)
+pub type QueryPlanRow = (i32, i32, i32, String);
+
+/// Runs a compiled query through EXPLAIN QUERY PLAN instead of running it.
+pub struct ExplainQueryPlan(pub EntryQuery);
+
+impl QueryFragment<Sqlite> for ExplainQueryPlan {
+    fn walk_ast(&self, mut out: AstPass<Sqlite>) -> QueryResult<()> {
+        out.push_sql("EXPLAIN QUERY PLAN ");
+        self.0.walk_ast(out.reborrow())
+    }
+}
+
+impl QueryId for ExplainQueryPlan {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl Query for ExplainQueryPlan {
+    type SqlType = (Integer, Integer, Integer, Text);
+}
+
+impl RunQueryDsl<SqliteConnection> for ExplainQueryPlan {}
+
+/// Turns the rows of EXPLAIN QUERY PLAN into lines indented by how deeply each step is nested.
+pub fn query_plan(rows: Vec<QueryPlanRow>) -> Vec<String> {
+    let mut depths = HashMap::<i32, usize>::new();
+
+    rows.into_iter().map(|(id, parent, _, detail)| {
+        let depth = depths.get(&parent).map(|d| d + 1).unwrap_or(0);
+        depths.insert(id, depth);
+        "  ".repeat(depth) + &detail
+    }).collect()
+}
+
+/// Replaces every saved query a query refers to with the query saved under that name, in parentheses.
+///
+/// `lookup` returns the text saved under a name, or None if nothing is saved under it.
+pub fn expand_saved_queries<F: FnMut(&str) -> Result<Option<String>, SqliteError>>(query: &OrQuery, mut lookup: F) -> Result<OrQuery, SqliteError> {
+    expand_or_query(query, &mut lookup, &mut Vec::new())
+}
+
+fn expand_or_query<F: FnMut(&str) -> Result<Option<String>, SqliteError>>(q: &OrQuery, lookup: &mut F, stack: &mut Vec<String>) -> Result<OrQuery, SqliteError> {
+    Ok(OrQuery {
+        and_query: expand_and_query(&q.and_query, lookup, stack)?,
+        next: match &q.next {
+            Some(s) => Some(Box::new(expand_or_query(s, lookup, stack)?)),
+            None => None
+        }
+    })
+}
+
+fn expand_and_query<F: FnMut(&str) -> Result<Option<String>, SqliteError>>(q: &AndQuery, lookup: &mut F, stack: &mut Vec<String>) -> Result<AndQuery, SqliteError> {
+    Ok(AndQuery {
+        factor: expand_factor(&q.factor, lookup, stack)?,
+        next: match &q.next {
+            Some(s) => Some(Box::new(expand_and_query(s, lookup, stack)?)),
+            None => None
+        }
+    })
+}
+
+fn expand_factor<F: FnMut(&str) -> Result<Option<String>, SqliteError>>(f: &Factor, lookup: &mut F, stack: &mut Vec<String>) -> Result<Factor, SqliteError> {
+    Ok(match f {
+        Factor::Query(q) => Factor::Query(Box::new(expand_or_query(q, lookup, stack)?)),
+        Factor::Not(f) => Factor::Not(Box::new(expand_factor(f, lookup, stack)?)),
+        Factor::SavedQuery(name) => {
+            // the names of the saved queries being expanded, outermost first
+            if stack.contains(name) {
+                let cycle = stack.iter().chain(std::iter::once(name)).map(|n| format!("@q:{}", n)).into_vec().join(" -> ");
+                return Err(ApplicationError(format!("The saved query '{}' refers to itself ({}).", name, cycle)));
+            }
+
+            let text = match (lookup(name)?, stack.last()) {
+                (Some(s), _) => s,
+                // the saved query it was in was saved before this one was deleted
+                (None, Some(outer)) => return Err(ApplicationError(format!("The saved query '{}' refers to '@q:{}', which no longer exists. Type 'meta query show {}' to see it.", outer, name, outer))),
+                (None, None) => return Err(ApplicationError(format!("There is no saved query named '{}'. Type 'meta query list' to see the saved queries.", name)))
+            };
+
+            let saved = parse_text(&text)
+                .map_err(|e| ApplicationError(format!("The saved query '{}' could not be parsed: {}", name, e)))?;
+
+            stack.push(name.to_owned());
+            let expanded = expand_or_query(&saved, lookup, stack)?;
+            stack.pop();
+
+            Factor::Query(Box::new(expanded))
+        }
+        _ => f.clone()
+    })
+}
+
+/// Returns the names of the saved queries a query refers to directly, each once and in the order they first appear.
+pub fn saved_query_references(query: &OrQuery) -> Vec<&str> {
+    let mut ret = Vec::new();
+    or_query_references(query, &mut ret);
+    ret
+}
+
+fn or_query_references<'a>(q: &'a OrQuery, out: &mut Vec<&'a str>) {
+    let mut or_query = Some(q);
+
+    while let Some(oq) = or_query {
+        let mut and_query = Some(&oq.and_query);
+
+        while let Some(aq) = and_query {
+            factor_references(&aq.factor, out);
+            and_query = aq.next.as_deref();
+        }
+
+        or_query = oq.next.as_deref();
+    }
+}
+
+fn factor_references<'a>(f: &'a Factor, out: &mut Vec<&'a str>) {
+    match f {
+        Factor::SavedQuery(name) if !out.contains(&name.as_str()) => out.push(name),
+        Factor::Query(q) => or_query_references(q, out),
+        Factor::Not(f) | Factor::Quantified((_, f)) | Factor::Scoped((_, f)) => factor_references(f, out),
+        _ => {}
+    }
+}
+
+/// Compiles a query into a statement selecting every file and directory that satisfies it, in the order and page it asks for.
+///
+/// If the query is `inherited`, entries also have the metadata of the directories above them.
+///
+/// The rows it produces can be turned back into entries with `row_entry`.
+pub fn compile(query: &parse::Query) -> Result<EntryQuery, SqliteError> {
+    let mut q = EntryQuery::new(query.inherited);
+
+    if sort_columns(query).is_empty() && query.limit.is_none() && query.offset.is_none() {
+        q.select(&query.filter, &DIRECTORIES, None)?;
+        q.sql(" UNION ALL ");
+        q.select(&query.filter, &FILES, None)?;
+        return Ok(q);
+    }
+
+    q.sql("SELECT kind, id, directory_id, name, hash FROM ");
+    q.union(&query.filter, &sort_columns(query).into_iter().map(|x| x.0).into_vec())?;
+    q.order_and_page(query);
+
+    Ok(q)
+}
+
+/// Compiles a query into a statement counting how many of the entries that satisfy it have each value of a key.
+///
+/// Entries that don't have the key are counted under a NULL value. The most common values come first.
+pub fn compile_count(query: &parse::Query, key: &str) -> Result<CountQuery, SqliteError> {
+    let mut q = EntryQuery::new(query.inherited);
+    let mut columns = sort_columns(query).into_iter().map(|x| x.0).into_vec();
+    columns.push(Column::Key(key));
+    let value = format!("value_{}", columns.len() - 1);
+
+    q.sql(&format!("SELECT {}, COUNT(*) FROM ", value));
+
+    if query.limit.is_some() || query.offset.is_some() {
+        // only the entries on the page the query asks for are counted
+        q.sql("(SELECT * FROM ");
+        q.union(&query.filter, &columns)?;
+        q.order_and_page(query);
+        q.sql(")");
+    }
+    else {
+        q.union(&query.filter, &columns)?;
+    }
+
+    q.sql(&format!(" GROUP BY {} ORDER BY COUNT(*) DESC, {}", value, value_order(&value, false)));
+
+    Ok(CountQuery(q))
+}
+
+/// What a value_n column of the union holds for each entry.
+enum Column<'a> {
+    /// The entry's value for a key.
+    Key(&'a str),
+    /// How many edits the nearest of the entry's values for a key is from a value.
+    Distance(&'a str, &'a str),
+}
+
+/// Returns the columns a query's results are sorted on, in order, each paired with whether it's sorted descending.
+fn sort_columns(query: &parse::Query) -> Vec<(Column, bool)> {
+    if query.order_by.is_empty() {
+        // a query looking for values near something is ranked by how near they are unless it says otherwise
+        if let Some((k, v)) = first_near(&query.filter) {
+            return vec![(Column::Distance(k, v), false)];
+        }
+    }
+
+    query.order_by.iter().map(|k| (Column::Key(&k.key), k.descending)).into_vec()
+}
+
+/// Returns the key and value of the first 'near' in a query that isn't negated or applied to a key pattern.
+fn first_near(q: &OrQuery) -> Option<(&str, &str)> {
+    let mut or_query = Some(q);
+
+    while let Some(oq) = or_query {
+        let mut and_query = Some(&oq.and_query);
+
+        while let Some(aq) = and_query {
+            let found = match &aq.factor {
+                Factor::KeyNear((k, v, _)) => Some((k.as_str(), v.as_str())),
+                Factor::Query(q) => first_near(q),
+                _ => None
+            };
+
+            if found.is_some() {
+                return found;
+            }
+
+            and_query = aq.next.as_deref();
+        }
+
+        or_query = oq.next.as_deref();
+    }
+
+    None
+}
+
+pub fn row_entry(row: EntryRow) -> Entry {
+    let (kind, id, directory_id, name, hash) = row;
+
+    match kind {
+        FILE_KIND => Entry::File(File {
+            id,
+            directory_id: directory_id.expect("A file row is missing its directory_id."),
+            filename: name,
+            hash: hash.unwrap_or_default(),
+        }),
+        _ => Entry::Directory(Directory {
+            id,
+            path: name,
+        })
+    }
+}
+
+#[test]
+fn test_like_pattern() {
+    let cases = [
+        ("J*", "J%"),
+        ("client-?", "client-_"),
+        ("100%", "100\\%"),
+        ("snake_case", "snake\\_case"),
+        ("\\*star\\?", "*star?"),
+        ("back\\\\slash", "back\\\\slash"),
+        ("trailing\\", "trailing\\\\")
+    ];
+
+    for (pattern, exp) in cases.iter() {
+        assert_eq!(like_pattern(pattern), *exp);
+    }
+}
+
+#[test]
+fn test_glob_pattern() {
+    let cases = [
+        ("J*", "J*"),
+        ("client-[0-9]", "client-[0-9]"),
+        ("\\*star\\?", "[*]star[?]"),
+        ("\\[x]", "[[]x]"),
+        ("back\\\\slash", "back\\slash")
+    ];
+
+    for (pattern, exp) in cases.iter() {
+        assert_eq!(glob_pattern(pattern), *exp);
+    }
+}
+
+#[test]
+fn test_expand_saved_queries() {
+    let saved = |n: &str| Ok(match n {
+        "pdfs" => Some("@ext = pdf".to_owned()),
+        "unreviewed" => Some("not reviewed and @q:pdfs".to_owned()),
+        "a" => Some("x or @q:b".to_owned()),
+        "b" => Some("@q:a".to_owned()),
+        "dangling" => Some("x and @q:missing".to_owned()),
+        _ => None
+    });
+
+    let expanded = match expand_saved_queries(&parse_text("@q:unreviewed and priority = high").ok().unwrap(), saved) {
+        Ok(q) => q,
+        Err(e) => panic!("{}", e)
+    };
+    assert_eq!(expanded, parse_text(r#"(not reviewed and (@ext = "pdf")) and priority = "high""#).ok().unwrap());
+
+    assert!(expand_saved_queries(&parse_text("@q:missing").ok().unwrap(), saved).is_err());
+    assert!(expand_saved_queries(&parse_text("y and @q:a").ok().unwrap(), saved).is_err());
+
+    match expand_saved_queries(&parse_text("@q:dangling").ok().unwrap(), saved) {
+        Err(ApplicationError(e)) => assert!(e.starts_with("The saved query 'dangling' refers to '@q:missing'"), "{}", e),
+        _ => panic!("Expanding @q:dangling should have failed")
+    }
+}
+
+#[test]
+fn test_saved_query_references() {
+    let query = parse_text("@q:a or not (x = y and @q:b) or any * = z or @q:a").ok().unwrap();
+    assert_eq!(saved_query_references(&query), vec!["a", "b"]);
+    assert!(saved_query_references(&parse_text("x = y").ok().unwrap()).is_empty());
+}
+
+/// Opens an in-memory database with the tables queries run against, holding
+///
+///     /            client = root
+///     /top.txt     rating = 4, title = Report_final, reviewer = alice, author = Jonathan
+///     /a           client = a, status = done
+///     /a/b         status = todo
+///     /a/b/x.txt   status = review, rating = 10, title = report 100%, reviewer = bob, editor = bob
+///     /a/b/y.txt   rating = 10a, title = draft*, reviewer = dave, editor = carol, author = Jon
+///     /ab
+///     /ab/z.txt    rating = 9, title = aaa...a! (30 a's), author = Jonathon
+#[cfg(test)]
+fn test_connection() -> SqliteConnection {
+    use diesel::connection::SimpleConnection;
+
+    let conn = SqliteConnection::establish(":memory:").expect("Failed to open an in-memory database.");
+    super::functions::register(&conn).expect("Failed to register the SQL functions.");
+
+    conn.batch_execute(r#"
+        CREATE TABLE Directories (id INTEGER NOT NULL PRIMARY KEY, path TEXT NOT NULL UNIQUE);
+        CREATE TABLE Files (id INTEGER NOT NULL PRIMARY KEY, directory_id INTEGER NOT NULL, filename TEXT NOT NULL, hash BLOB NOT NULL);
+        CREATE TABLE DirectoryMetadata (id INTEGER NOT NULL PRIMARY KEY, directory_id INTEGER NOT NULL, key TEXT NOT NULL, value TEXT NOT NULL);
+        CREATE TABLE FileMetadata (id INTEGER NOT NULL PRIMARY KEY, file_id INTEGER NOT NULL, key TEXT NOT NULL, value TEXT NOT NULL);
+
+        INSERT INTO Directories (id, path) VALUES (1, '/'), (2, '/a'), (3, '/a/b'), (4, '/ab');
+        INSERT INTO Files (id, directory_id, filename, hash) VALUES (1, 1, 'top.txt', X'00'), (2, 3, 'x.txt', X'00'), (3, 3, 'y.txt', X'00'), (4, 4, 'z.txt', X'00');
+        INSERT INTO DirectoryMetadata (directory_id, key, value) VALUES (1, 'client', 'root'), (2, 'client', 'a'), (2, 'status', 'done'), (3, 'status', 'todo');
+        INSERT INTO FileMetadata (file_id, key, value) VALUES (2, 'status', 'review'),
+            (1, 'rating', '4'), (1, 'title', 'Report_final'), (1, 'reviewer', 'alice'), (1, 'author', 'Jonathan'),
+            (2, 'rating', '10'), (2, 'title', 'report 100%'), (2, 'reviewer', 'bob'), (2, 'editor', 'bob'),
+            (3, 'rating', '10a'), (3, 'title', 'draft*'), (3, 'reviewer', 'dave'), (3, 'editor', 'carol'), (3, 'author', 'Jon'),
+            (4, 'rating', '9'), (4, 'title', 'aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa!'), (4, 'author', 'Jonathon');
+    "#).expect("Failed to fill the in-memory database.");
+
+    conn
+}
+
+/// Returns the names of the entries a query matches in the test database, in the order the query returns them.
+#[cfg(test)]
+fn results(conn: &SqliteConnection, query: &str, inherited: bool) -> Vec<String> {
+    let mut query = parse::parse_query_text(query).expect("Failed to parse the test query.");
+    query.inherited = inherited;
+
+    let rows = compile(&query).ok().expect("Failed to compile the test query.")
+        .load::<EntryRow>(conn).expect("Failed to run the test query.");
+
+    rows.into_iter()
+        .map(|r| match row_entry(r) {
+            Entry::File(f) => f.filename,
+            Entry::Directory(d) => d.path
+        })
+        .into_vec()
+}
+
+/// Returns the names of the entries a query matches in the test database, sorted.
+#[cfg(test)]
+fn matches(conn: &SqliteConnection, query: &str, inherited: bool) -> Vec<String> {
+    let mut names = results(conn, query, inherited);
+    names.sort();
+    names
+}
+
+#[test]
+fn test_keys_and_values() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "status", false), vec!["/a", "/a/b", "x.txt"]);
+    assert_eq!(matches(&conn, "status = todo or client = root", false), vec!["/", "/a/b"]);
+    assert_eq!(matches(&conn, "rating and reviewer = bob", false), vec!["x.txt"]);
+    assert_eq!(matches(&conn, "status in (done, review)", false), vec!["/a", "x.txt"]);
+    // without inheritance, /a/b doesn't have /a's client
+    assert!(matches(&conn, "status = todo and client = a", false).is_empty());
+}
+
+#[test]
+fn test_not() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "not status", false), vec!["/", "/ab", "top.txt", "y.txt", "z.txt"]);
+    // only entries that have the key can have a different value for it
+    assert_eq!(matches(&conn, "status != done", false), vec!["/a/b", "x.txt"]);
+    assert_eq!(matches(&conn, "status is not done", false), vec!["/a/b", "x.txt"]);
+    assert_eq!(matches(&conn, "not (status or client)", false), vec!["/ab", "top.txt", "y.txt", "z.txt"]);
+    // 'not' applies to the factor right after it, not to the whole 'and'
+    assert_eq!(matches(&conn, "not status and rating", false), vec!["top.txt", "y.txt", "z.txt"]);
+}
+
+#[test]
+fn test_ordered_comparisons() {
+    let conn = test_connection();
+
+    // 10 > 9 even though "10" < "9", and 10a doesn't look like a number, so it's greater than any number
+    assert_eq!(matches(&conn, "rating > 9", false), vec!["x.txt", "y.txt"]);
+    assert_eq!(matches(&conn, "rating >= 10", false), vec!["x.txt", "y.txt"]);
+    assert_eq!(matches(&conn, "rating < 10", false), vec!["top.txt", "z.txt"]);
+    assert_eq!(matches(&conn, "rating <= 4", false), vec!["top.txt"]);
+    assert_eq!(matches(&conn, "rating < 10a", false), vec!["top.txt", "x.txt", "z.txt"]);
+}
+
+#[test]
+fn test_matches() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "title matches '^report'", false), vec!["x.txt"]);
+    assert_eq!(matches(&conn, "title imatches '^report'", false), vec!["top.txt", "x.txt"]);
+    assert_eq!(matches(&conn, "title matches '[0-9]+%'", false), vec!["x.txt"]);
+
+    let invalid = parse::parse_query_text("title matches '(report'").ok().unwrap();
+    assert!(compile(&invalid).is_err());
+
+    // the lookahead keeps the regex from being handed to the non-backtracking engine, so z.txt's title backtracks past the limit
+    let runaway = parse::parse_query_text("title matches '^(?=a)(a+)+$'").ok().unwrap();
+    let error = compile(&runaway).ok().expect("Failed to compile the test query.")
+        .load::<EntryRow>(&conn)
+        .expect_err("A runaway regex should fail the query.");
+    assert!(error.to_string().contains("backtracked too many times"), "{}", error);
+}
+
+#[test]
+fn test_like_and_glob() {
+    let conn = test_connection();
+
+    // like ignores case and glob doesn't
+    assert_eq!(matches(&conn, "title like 'report*'", false), vec!["top.txt", "x.txt"]);
+    assert_eq!(matches(&conn, "title glob 'report*'", false), vec!["x.txt"]);
+    // LIKE's own wildcards are matched literally
+    assert_eq!(matches(&conn, "title like 'Report_*'", false), vec!["top.txt"]);
+    assert_eq!(matches(&conn, "title like '*100%'", false), vec!["x.txt"]);
+    assert!(matches(&conn, "title like '*0_'", false).is_empty());
+    // an escaped wildcard only matches itself
+    assert_eq!(matches(&conn, r"title glob 'draft\\*'", false), vec!["y.txt"]);
+    assert_eq!(matches(&conn, r"title like 'draft\\*'", false), vec!["y.txt"]);
+    assert!(matches(&conn, r"title glob 'report\\*'", false).is_empty());
+    assert_eq!(matches(&conn, "title glob 'draft?'", false), vec!["y.txt"]);
+}
+
+#[test]
+fn test_builtin_keys() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "@ext = txt", false), vec!["top.txt", "x.txt", "y.txt", "z.txt"]);
+    assert_eq!(matches(&conn, "@type = dir", false), vec!["/", "/a", "/a/b", "/ab"]);
+    assert_eq!(matches(&conn, "@name = b", false), vec!["/a/b"]);
+    assert_eq!(matches(&conn, "@path = '/a/b/x.txt'", false), vec!["x.txt"]);
+    assert_eq!(matches(&conn, "@path = '/top.txt'", false), vec!["top.txt"]);
+    assert_eq!(matches(&conn, "@hash = 00", false), vec!["top.txt", "x.txt", "y.txt", "z.txt"]);
+    // built-in keys combine with metadata keys like any other
+    assert_eq!(matches(&conn, "@ext = txt and rating > 9", false), vec!["x.txt", "y.txt"]);
+    assert!(matches(&conn, "@type = file and not rating", false).is_empty());
+}
+
+#[test]
+fn test_under_and_directly_in() {
+    let conn = test_connection();
+
+    // /ab isn't under /a, even though its path starts with it, and a directory isn't under itself
+    assert_eq!(matches(&conn, "under '/a'", false), vec!["/a/b", "x.txt", "y.txt"]);
+    assert_eq!(matches(&conn, "under '/'", false), vec!["/a", "/a/b", "/ab", "top.txt", "x.txt", "y.txt", "z.txt"]);
+    assert_eq!(matches(&conn, "directly in '/a'", false), vec!["/a/b"]);
+    assert_eq!(matches(&conn, "directly in '/'", false), vec!["/a", "/ab", "top.txt"]);
+    assert_eq!(matches(&conn, "under '/a' and rating > 9", false), vec!["x.txt", "y.txt"]);
+}
+
+#[test]
+fn test_order_limit_offset() {
+    let conn = test_connection();
+
+    // numbers sort numerically and before the values that aren't numbers, in either direction
+    assert_eq!(results(&conn, "rating order by rating", false), vec!["top.txt", "z.txt", "x.txt", "y.txt"]);
+    assert_eq!(results(&conn, "rating order by rating desc", false), vec!["x.txt", "z.txt", "top.txt", "y.txt"]);
+    assert_eq!(results(&conn, "rating order by rating limit 2 offset 1", false), vec!["z.txt", "x.txt"]);
+    assert_eq!(results(&conn, "rating order by rating offset 3", false), vec!["y.txt"]);
+    assert_eq!(results(&conn, "@type = dir order by @path desc", false), vec!["/ab", "/a/b", "/a", "/"]);
+    assert_eq!(results(&conn, "@ext = txt order by reviewer desc", false), vec!["y.txt", "x.txt", "top.txt", "z.txt"]);
+    // the entries without the key come last, ordered by path, so /ab/z.txt comes before /top.txt
+    assert_eq!(results(&conn, "@ext = txt order by editor", false), vec!["x.txt", "y.txt", "z.txt", "top.txt"]);
+    assert_eq!(results(&conn, "@ext = txt limit 1", false).len(), 1);
+}
+
+#[test]
+fn test_key_references() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "reviewer = $editor", false), vec!["x.txt"]);
+    assert_eq!(matches(&conn, "reviewer != $editor", false), vec!["y.txt"]);
+    assert_eq!(matches(&conn, "editor < $reviewer", false), vec!["y.txt"]);
+    // an entry missing either key never matches, whichever way they're compared
+    assert!(matches(&conn, "not reviewer = $editor", false).contains(&"top.txt".to_owned()));
+    assert!(!matches(&conn, "reviewer != $editor", false).contains(&"top.txt".to_owned()));
+    // a built-in key can be referred to as well, and directories have no title to compare with their name
+    assert_eq!(matches(&conn, "title < $@name", false), vec!["top.txt", "x.txt", "y.txt", "z.txt"]);
+}
+
+#[test]
+fn test_near() {
+    let conn = test_connection();
+
+    // ranked by distance: Jonathan is 0 away, Jonathon 1 and Jon 5
+    assert_eq!(results(&conn, "author near Jonathan", false), vec!["top.txt", "z.txt"]);
+    assert_eq!(results(&conn, "author near Jonathan within 5", false), vec!["top.txt", "z.txt", "y.txt"]);
+    assert_eq!(results(&conn, "author near Jonathon within 0", false), vec!["z.txt"]);
+    assert_eq!(matches(&conn, "not author near Jonathan", false), vec!["/", "/a", "/a/b", "/ab", "x.txt", "y.txt"]);
+}
+
+#[test]
+fn test_inherited_nearest_directory_wins() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "status = todo", true), vec!["/a/b", "y.txt"]);
+    assert_eq!(matches(&conn, "status = done", true), vec!["/a"]);
+}
+
+#[test]
+fn test_inherited_own_key_hides_directory_key() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "status = review", true), vec!["x.txt"]);
+    assert!(!matches(&conn, "status = todo", true).contains(&"x.txt".to_owned()));
+}
+
+#[test]
+fn test_inherited_file_in_root_directory() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "client = root", true), vec!["/", "/ab", "top.txt", "z.txt"]);
+}
+
+#[test]
+fn test_inherited_sibling_with_common_prefix() {
+    let conn = test_connection();
+
+    // /a is not above /ab or /ab/z.txt, even though their paths start with it
+    assert_eq!(matches(&conn, "client = a", true), vec!["/a", "/a/b", "x.txt", "y.txt"]);
+    assert!(matches(&conn, "status", true).iter().all(|n| n != "/ab" && n != "z.txt"));
+}
+
+#[test]
+fn test_scoped_parent() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "parent.client = root", false), vec!["/a", "/ab", "top.txt"]);
+    assert_eq!(matches(&conn, "parent.status = todo", false), vec!["x.txt", "y.txt"]);
+    assert!(matches(&conn, "parent has client", false).iter().all(|n| n != "/"));
+}
+
+#[test]
+fn test_scoped_ancestor() {
+    let conn = test_connection();
+
+    assert_eq!(matches(&conn, "ancestor.client = root", false), vec!["/a", "/a/b", "/ab", "top.txt", "x.txt", "y.txt", "z.txt"]);
+    // a directory isn't its own ancestor, and /a is not above /ab
+    assert_eq!(matches(&conn, "ancestor.client = a", false), vec!["/a/b", "x.txt", "y.txt"]);
+}
+
+#[test]
+fn test_scoped_ignores_inheritance() {
+    let conn = test_connection();
+
+    // /a/b would inherit client = a, but the scope only looks at the directory's own metadata
+    assert_eq!(matches(&conn, "parent.client = a", true), vec!["/a/b"]);
+}
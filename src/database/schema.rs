@@ -34,6 +34,14 @@ table! {
     }
 }
 
+table! {
+    SavedQueries (id) {
+        id -> Integer,
+        name -> Text,
+        query -> Text,
+    }
+}
+
 joinable!(DirectoryMetadata -> Directories (directory_id));
 joinable!(FileMetadata -> Files (file_id));
 joinable!(Files -> Directories (directory_id));
@@ -43,4 +51,5 @@ allow_tables_to_appear_in_same_query!(
     DirectoryMetadata,
     FileMetadata,
     Files,
+    SavedQueries,
 );
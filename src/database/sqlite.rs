@@ -1,18 +1,23 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::iter::FromIterator;
 use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use diesel::{delete, insert_into, insert_or_ignore_into, update};
+use diesel::connection::TransactionManager;
+use diesel::{debug_query, delete, insert_into, insert_or_ignore_into, update};
 use diesel::prelude::*;
-use diesel::sqlite::SqliteConnection;
+use diesel::sqlite::{Sqlite, SqliteConnection};
 
+use crate::cli::query::parse::Query;
 use crate::database::sqlite::SqliteError::*;
 use crate::format::prettify::PrettyPaths;
 use crate::linq::collectors::IntoVec;
 
-use super::database::{Database, Entry};
+use super::database::{Database, Entry, QueryExplanation};
+use super::functions;
 use super::models::*;
 use super::path::Path;
+use super::query::{compile, compile_count, expand_saved_queries, query_plan, row_entry, CountRow, EntryQuery, EntryRow, ExplainQueryPlan, QueryPlanRow};
 
 embed_migrations!();
 
@@ -21,6 +26,15 @@ pub enum SqliteError {
     ApplicationError(String),
 }
 
+impl fmt::Display for SqliteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError(e) => write!(f, "{}", e),
+            ApplicationError(s) => write!(f, "{}", s)
+        }
+    }
+}
+
 trait ToSqlite<T> {
     fn into_db_err(self) -> Result<T, SqliteError>;
 }
@@ -65,11 +79,37 @@ impl UnsynchronizedSqliteDatabase {
             Err(e) => return Err(ApplicationError(format!("Failed to run migrations: {:?}", e)))
         }
 
+        match functions::register(&conn) {
+            Ok(_) => {}
+            Err(e) => return Err(ApplicationError(format!("Failed to register SQL functions: {:?}", e)))
+        }
+
         Ok(UnsynchronizedSqliteDatabase { conn })
     }
+
+    /// Expands the saved queries a query refers to and compiles it.
+    fn compile_query(&self, query: &Query) -> Result<EntryQuery, SqliteError> {
+        compile(&self.expand_query(query)?)
+    }
+
+    fn expand_query(&self, query: &Query) -> Result<Query, SqliteError> {
+        let filter = expand_saved_queries(&query.filter, |n| Ok(self.saved_query(n)?.map(|q| q.query)))?;
+
+        Ok(Query { filter, ..query.clone() })
+    }
 }
 
 impl<'a> Database<'a, SqliteError> for UnsynchronizedSqliteDatabase {
+    fn transaction<T, F: FnOnce() -> Result<T, SqliteError>>(&self, f: F) -> Result<T, SqliteError> {
+        // SQLite transactions can't be nested, so one started inside of another becomes a savepoint
+        if TransactionManager::<SqliteConnection>::get_transaction_depth(self.conn.transaction_manager()) > 0 {
+            self.conn.transaction(f)
+        }
+        else {
+            self.conn.immediate_transaction(f)
+        }
+    }
+
     fn file_directory(&self, f: &File) -> Result<Directory, SqliteError> {
         use super::schema::Files::dsl::*;
         use super::schema::Directories::dsl::*;
@@ -221,6 +261,82 @@ impl<'a> Database<'a, SqliteError> for UnsynchronizedSqliteDatabase {
         })
     }
 
+    fn entry_metadata_inherited<B: FromIterator<(String, String, Option<Directory>)>>(&self, entry: &Entry) -> Result<B, SqliteError> {
+        Ok(self.entries_metadata_inherited::<Vec<_>, _>(std::iter::once(entry))?
+            .pop()
+            .map(|x| x.1)
+            .unwrap_or_default()
+            .into_iter()
+            .collect())
+    }
+
+    fn entries_metadata_inherited<'b, B: FromIterator<(Entry, Vec<(String, String, Option<Directory>)>)>, I: Iterator<Item=&'b Entry>>(&self, entries: I) -> Result<B, SqliteError> {
+        use super::schema::Directories::dsl::*;
+        use super::schema::DirectoryMetadata;
+
+        let own = self.entries_metadata::<Vec<_>, _>(entries)?;
+
+        let file_dirs = own.iter()
+            .filter_map(|(e, _)| match e {
+                Entry::File(f) => Some(f.directory_id),
+                Entry::Directory(_) => None
+            })
+            .into_vec();
+
+        let dir_paths = Directories.filter(id.eq_any(file_dirs))
+            .load::<Directory>(&self.conn).into_db_err()?
+            .into_iter()
+            .map(|d| (d.id, d.path))
+            .collect::<HashMap<_, _>>();
+
+        // the paths of the directories each entry inherits from, nearest first
+        let ancestors = own.iter()
+            .map(|(e, _)| {
+                let mut ret = Vec::new();
+                let mut next = match e {
+                    Entry::File(f) => dir_paths.get(&f.directory_id).cloned(),
+                    Entry::Directory(d) if d.path != "/" => Some(Path::parent_str(&d.path).to_owned()),
+                    Entry::Directory(_) => None
+                };
+
+                while let Some(p) = next {
+                    next = if p == "/" { None } else { Some(Path::parent_str(&p).to_owned()) };
+                    ret.push(p);
+                }
+                ret
+            })
+            .into_vec();
+
+        // the metadata of every directory any of the entries inherits from, loaded all at once
+        let paths = ancestors.iter().flatten().map(|x| x.as_str()).collect::<HashSet<_>>();
+        let mut by_path = HashMap::<String, Vec<(Directory, DirectoryKeyValuePair)>>::new();
+
+        for (d, kv) in Directories.filter(path.eq_any(paths.into_iter().into_vec()))
+            .inner_join(DirectoryMetadata::table)
+            .load::<(Directory, DirectoryKeyValuePair)>(&self.conn).into_db_err()? {
+            by_path.entry(d.path.clone()).or_default().push((d, kv));
+        }
+
+        Ok(own.into_iter()
+            .zip(ancestors)
+            .map(|((e, kv), ancestors)| {
+                let mut seen = HashSet::new();
+                let own = kv.into_iter()
+                    .map(|(k, v)| (k, v, None));
+                let inherited = ancestors.iter()
+                    .flat_map(|p| by_path.get(p).into_iter().flatten())
+                    .map(|(d, kv)| (kv.key.clone(), kv.value.clone(), Some(d.clone())));
+
+                // a key the entry has itself, or that a nearer directory has, hides the same key further up
+                let kv = own.chain(inherited)
+                    .filter(|x| seen.insert(x.0.clone()))
+                    .collect();
+
+                (e, kv)
+            })
+            .collect())
+    }
+
     fn entries_metadata<'b, B: FromIterator<(Entry, Vec<(String, String)>)>, I: Iterator<Item=&'b Entry>>(&self, entries: I) -> Result<B, SqliteError> {
         let (f, d) = Entry::iter_split(entries.map(|x| x.clone()));
 
@@ -278,7 +394,7 @@ impl<'a> Database<'a, SqliteError> for UnsynchronizedSqliteDatabase {
     }
 
     fn entries_metadata_set<'b, B: FromIterator<(Entry, Option<String>)>, I: Iterator<Item=&'b Entry>>(&self, entries: I, k: &str, v: Option<&str>) -> Result<B, SqliteError> {
-        self.conn.immediate_transaction(|| {
+        self.transaction(|| {
             let mut ret = Vec::<(Entry, Option<String>)>::new();
 
             for entry in entries {
@@ -286,7 +402,7 @@ impl<'a> Database<'a, SqliteError> for UnsynchronizedSqliteDatabase {
             }
 
             Ok(ret.into_iter().collect())
-        }).into_db_err()
+        })
     }
 
     fn entries_metadata_clear<'b, I: Iterator<Item=&'b Entry>>(&self, entries: I) -> Result<usize, SqliteError> {
@@ -415,6 +531,61 @@ impl<'a> Database<'a, SqliteError> for UnsynchronizedSqliteDatabase {
         )
     }
 
+    fn query_entries<B: FromIterator<Entry>>(&self, query: &Query) -> Result<B, SqliteError> {
+        let rows = self.compile_query(query)?
+            .load::<EntryRow>(&self.conn).into_db_err()?;
+
+        Ok(rows.into_iter()
+            .map(row_entry)
+            .collect())
+    }
+
+    fn explain_query(&self, query: &Query) -> Result<QueryExplanation, SqliteError> {
+        let compiled = self.compile_query(query)?;
+        let sql = debug_query::<Sqlite, _>(&compiled).to_string();
+
+        let plan = ExplainQueryPlan(compiled)
+            .load::<QueryPlanRow>(&self.conn).into_db_err()?;
+
+        Ok(QueryExplanation {
+            sql,
+            plan: query_plan(plan),
+        })
+    }
+
+    fn count_values<B: FromIterator<(Option<String>, usize)>>(&self, query: &Query, k: &str) -> Result<B, SqliteError> {
+        let rows = compile_count(&self.expand_query(query)?, k)?
+            .load::<CountRow>(&self.conn).into_db_err()?;
+
+        Ok(rows.into_iter()
+            .map(|(value, count)| (value, count as usize))
+            .collect())
+    }
+
+    fn metadata_keys<B: FromIterator<String>>(&self) -> Result<B, SqliteError> {
+        let file_keys = {
+            use super::schema::FileMetadata::dsl::*;
+
+            FileMetadata.select(key)
+                .distinct()
+                .load::<String>(&self.conn).into_db_err()?
+        };
+
+        let dir_keys = {
+            use super::schema::DirectoryMetadata::dsl::*;
+
+            DirectoryMetadata.select(key)
+                .distinct()
+                .load::<String>(&self.conn).into_db_err()?
+        };
+
+        Ok(file_keys.into_iter()
+            .chain(dir_keys.into_iter())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect())
+    }
+
     fn get_entry(&self, p: &str) -> Result<Option<Entry>, SqliteError> {
         use super::schema::Directories::dsl::*;
         use super::schema::Files::dsl::*;
@@ -481,6 +652,14 @@ impl<'a> Database<'a, SqliteError> for UnsynchronizedSqliteDatabase {
             .collect())
     }
 
+    fn entry_path(&self, entry: &Entry) -> Result<String, SqliteError> {
+        match entry {
+            Entry::Directory(d) => Ok(d.path.clone()),
+            // trimmed so files in the root directory don't get a double slash
+            Entry::File(f) => Ok(format!("{}/{}", self.file_directory(f)?.path.trim_end_matches('/'), f.filename))
+        }
+    }
+
     fn add_directory(&self, p: &str) -> Result<(Directory, bool), SqliteError> {
         use super::schema::Directories::dsl::*;
 
@@ -507,8 +686,9 @@ impl<'a> Database<'a, SqliteError> for UnsynchronizedSqliteDatabase {
         }
 
         p.pop();
+        // stops at the first directory above that's already in the database, since the ones above that are too
         while p.str().len() > 1 {
-            let res = insert_into(Directories)
+            let res = insert_or_ignore_into(Directories)
                 .values(NewDirectory {
                     path: p.str()
                 })
@@ -572,7 +752,8 @@ impl<'a> Database<'a, SqliteError> for UnsynchronizedSqliteDatabase {
             })
             .execute(&self.conn).into_db_err()?;
 
-        let file = Files.filter(filename.eq(p.filename()))
+        let file = Files.filter(directory_id.eq(dir.id))
+            .filter(filename.eq(p.filename()))
             .first::<File>(&self.conn).into_db_err()?;
 
         return Ok((file, res > 0));
@@ -651,6 +832,53 @@ impl<'a> Database<'a, SqliteError> for UnsynchronizedSqliteDatabase {
 
         Ok(sz)
     }
+
+    fn saved_query(&self, n: &str) -> Result<Option<SavedQuery>, SqliteError> {
+        use super::schema::SavedQueries::dsl::*;
+
+        SavedQueries.filter(name.eq(n))
+            .first::<SavedQuery>(&self.conn)
+            .optional()
+            .into_db_err()
+    }
+
+    fn saved_queries<B: FromIterator<SavedQuery>>(&self) -> Result<B, SqliteError> {
+        use super::schema::SavedQueries::dsl::*;
+
+        Ok(SavedQueries.order(name)
+            .load::<SavedQuery>(&self.conn).into_db_err()?
+            .into_iter()
+            .collect())
+    }
+
+    fn save_query(&self, n: &str, q: &str) -> Result<bool, SqliteError> {
+        use super::schema::SavedQueries::dsl::*;
+
+        let existed = self.saved_query(n)?.is_some();
+
+        if existed {
+            update(SavedQueries.filter(name.eq(n)))
+                .set(query.eq(q))
+                .execute(&self.conn).into_db_err()?;
+        }
+        else {
+            insert_into(SavedQueries)
+                .values(NewSavedQuery {
+                    name: n,
+                    query: q
+                })
+                .execute(&self.conn).into_db_err()?;
+        }
+
+        Ok(existed)
+    }
+
+    fn delete_saved_query(&self, n: &str) -> Result<bool, SqliteError> {
+        use super::schema::SavedQueries::dsl::*;
+
+        Ok(delete(SavedQueries.filter(name.eq(n)))
+            .execute(&self.conn).into_db_err()? > 0)
+    }
 }
 
 pub struct SqliteDatabase {
@@ -660,6 +888,7 @@ pub struct SqliteDatabase {
     file_meta_lock: RwLock<i32>,
     dir_lock: RwLock<i32>,
     dir_meta_lock: RwLock<i32>,
+    saved_query_lock: RwLock<i32>,
 }
 
 enum LockGuard<'a> {
@@ -673,6 +902,7 @@ struct SqliteDatabaseLockContext<'a> {
     file_meta: LockGuard<'a>,
     dir: LockGuard<'a>,
     dir_meta: LockGuard<'a>,
+    saved_query: LockGuard<'a>,
 }
 
 enum Lock {
@@ -680,6 +910,7 @@ enum Lock {
     FileMeta,
     Dir,
     DirMeta,
+    SavedQuery,
 }
 
 enum LockMode {
@@ -696,6 +927,7 @@ impl SqliteDatabase {
             file_meta_lock: RwLock::new(0),
             dir_lock: RwLock::new(0),
             dir_meta_lock: RwLock::new(0),
+            saved_query_lock: RwLock::new(0),
         })
     }
 
@@ -708,6 +940,7 @@ impl SqliteDatabase {
             file_meta: LockGuard::Empty,
             dir: LockGuard::Empty,
             dir_meta: LockGuard::Empty,
+            saved_query: LockGuard::Empty,
         };
 
         let _ = self.lock_mtx.lock().expect("Meta-mutex was poisoned.");
@@ -717,7 +950,8 @@ impl SqliteDatabase {
                 File => (&mut ret.file, &self.file_lock),
                 FileMeta => (&mut ret.file_meta, &self.file_meta_lock),
                 Dir => (&mut ret.dir, &self.dir_lock),
-                DirMeta => (&mut ret.dir_meta, &self.dir_meta_lock)
+                DirMeta => (&mut ret.dir_meta, &self.dir_meta_lock),
+                SavedQuery => (&mut ret.saved_query, &self.saved_query_lock)
             };
 
             *r = match mode {
@@ -731,6 +965,11 @@ impl SqliteDatabase {
 }
 
 impl<'a> Database<'a, SqliteError> for SqliteDatabase {
+    fn transaction<T, F: FnOnce() -> Result<T, SqliteError>>(&self, f: F) -> Result<T, SqliteError> {
+        // no locks are taken here, since the calls made inside of f take their own
+        self.usd.transaction(f)
+    }
+
     fn file_directory(&self, f: &File) -> Result<Directory, SqliteError> {
         use self::Lock::*;
         use self::LockMode::*;
@@ -776,6 +1015,24 @@ impl<'a> Database<'a, SqliteError> for SqliteDatabase {
         self.usd.entry_metadata_clear(entry)
     }
 
+    fn entry_metadata_inherited<B: FromIterator<(String, String, Option<Directory>)>>(&self, entry: &Entry) -> Result<B, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(File, Read), (Dir, Read), (FileMeta, Read), (DirMeta, Read)]);
+
+        self.usd.entry_metadata_inherited(entry)
+    }
+
+    fn entries_metadata_inherited<'b, B: FromIterator<(Entry, Vec<(String, String, Option<Directory>)>)>, I: Iterator<Item=&'b Entry>>(&self, entries: I) -> Result<B, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(File, Read), (Dir, Read), (FileMeta, Read), (DirMeta, Read)]);
+
+        self.usd.entries_metadata_inherited(entries)
+    }
+
     fn entries_metadata<'b, B: FromIterator<(Entry, Vec<(String, String)>)>, I: Iterator<Item=&'b Entry>>(&self, entries: I) -> Result<B, SqliteError> {
         use self::Lock::*;
         use self::LockMode::*;
@@ -848,6 +1105,42 @@ impl<'a> Database<'a, SqliteError> for SqliteDatabase {
         self.usd.directory_entries_with_key_and_value(d, key, value)
     }
 
+    fn query_entries<B: FromIterator<Entry>>(&self, query: &Query) -> Result<B, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(File, Read), (Dir, Read), (FileMeta, Read), (DirMeta, Read), (SavedQuery, Read)]);
+
+        self.usd.query_entries(query)
+    }
+
+    fn explain_query(&self, query: &Query) -> Result<QueryExplanation, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(File, Read), (Dir, Read), (FileMeta, Read), (DirMeta, Read), (SavedQuery, Read)]);
+
+        self.usd.explain_query(query)
+    }
+
+    fn count_values<B: FromIterator<(Option<String>, usize)>>(&self, query: &Query, key: &str) -> Result<B, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(File, Read), (Dir, Read), (FileMeta, Read), (DirMeta, Read), (SavedQuery, Read)]);
+
+        self.usd.count_values(query, key)
+    }
+
+    fn metadata_keys<B: FromIterator<String>>(&self) -> Result<B, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(FileMeta, Read), (DirMeta, Read)]);
+
+        self.usd.metadata_keys()
+    }
+
     fn get_entry(&self, path: &str) -> Result<Option<Entry>, SqliteError> {
         use self::Lock::*;
         use self::LockMode::*;
@@ -866,6 +1159,15 @@ impl<'a> Database<'a, SqliteError> for SqliteDatabase {
         self.usd.get_entries(paths)
     }
 
+    fn entry_path(&self, entry: &Entry) -> Result<String, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(File, Read), (Dir, Read)]);
+
+        self.usd.entry_path(entry)
+    }
+
     fn add_directory(&self, path: &str) -> Result<(Directory, bool), SqliteError> {
         use self::Lock::*;
         use self::LockMode::*;
@@ -919,4 +1221,71 @@ impl<'a> Database<'a, SqliteError> for SqliteDatabase {
 
         self.usd.remove_entries(entries)
     }
+
+    fn saved_query(&self, name: &str) -> Result<Option<SavedQuery>, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(SavedQuery, Read)]);
+
+        self.usd.saved_query(name)
+    }
+
+    fn saved_queries<B: FromIterator<SavedQuery>>(&self) -> Result<B, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(SavedQuery, Read)]);
+
+        self.usd.saved_queries()
+    }
+
+    fn save_query(&self, name: &str, query: &str) -> Result<bool, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(SavedQuery, Write)]);
+
+        self.usd.save_query(name, query)
+    }
+
+    fn delete_saved_query(&self, name: &str) -> Result<bool, SqliteError> {
+        use self::Lock::*;
+        use self::LockMode::*;
+
+        let _ = self.ctx(&[(SavedQuery, Write)]);
+
+        self.usd.delete_saved_query(name)
+    }
+}
+
+#[test]
+fn test_entries_metadata_inherited() {
+    let db = SqliteDatabase::new(":memory:").ok().expect("Failed to open an in-memory database.");
+    let entry = |p: &str| db.get_entry(p).ok().expect("Failed to get an entry.").expect("An entry is missing.");
+
+    db.add_file("/a/b/x.txt", &[0]).ok().expect("Failed to add a file.");
+    db.add_file("/top.txt", &[0]).ok().expect("Failed to add a file.");
+
+    for (p, k, v) in &[("/", "client", "root"), ("/a", "client", "a"), ("/a", "status", "done"), ("/a/b", "status", "todo"), ("/a/b/x.txt", "owner", "bob")] {
+        db.entry_metadata_set(&entry(p), k, Some(v)).ok().expect("Failed to set a value.");
+    }
+
+    let entries = vec![entry("/a/b/x.txt"), entry("/top.txt"), entry("/a/b"), entry("/")];
+    let metadata = db.entries_metadata_inherited::<Vec<_>, _>(entries.iter()).ok().expect("Failed to get the inherited metadata.")
+        .into_iter()
+        .map(|(e, kv)| {
+            let mut kv = kv.into_iter().map(|(k, v, d)| (k, v, d.map(|d| d.path))).into_vec();
+            kv.sort();
+            (db.entry_path(&e).ok().expect("Failed to get a path."), kv)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let kv = |k: &str, v: &str, d: Option<&str>| (k.to_owned(), v.to_owned(), d.map(|d| d.to_owned()));
+
+    assert_eq!(metadata.len(), 4);
+    assert_eq!(metadata["/a/b/x.txt"], vec![kv("client", "a", Some("/a")), kv("owner", "bob", None), kv("status", "todo", Some("/a/b"))]);
+    assert_eq!(metadata["/top.txt"], vec![kv("client", "root", Some("/"))]);
+    assert_eq!(metadata["/a/b"], vec![kv("client", "a", Some("/a")), kv("status", "todo", None)]);
+    assert_eq!(metadata["/"], vec![kv("client", "root", None)]);
 }
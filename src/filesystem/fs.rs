@@ -1,12 +1,36 @@
 use std::env::{current_dir, set_current_dir};
-use std::io::{Error, ErrorKind, Result};
+use std::io::{copy, Error, ErrorKind, Read, Result};
 use std::path::Path;
-use std::fs::{DirEntry, ReadDir, read_dir};
+use std::fs::{DirEntry, Metadata, ReadDir, metadata, read_dir};
 use std::thread;
+use sha2::{Digest, Sha256};
 use crate::linq::collectors::IntoVec;
 
 pub const DB_NAME: &'static str = ".meta.db";
 
+/// Returns the filesystem metadata for a path stored in the database.
+///
+/// Stored paths are relative to the directory containing the database, so this expects the current directory to be that directory (see `reposition_to_db`).
+pub fn entry_metadata(path: &str) -> Result<Metadata> {
+    let relative = path.trim_start_matches('/');
+
+    metadata(if relative.is_empty() { "." } else { relative })
+}
+
+/// Returns the hash stored with a file in the database, which is the SHA-256 digest of its contents.
+///
+/// Like `entry_metadata`, this takes a stored path and expects the current directory to be the one containing the database.
+pub fn file_hash(path: &str) -> Result<Vec<u8>> {
+    contents_hash(std::fs::File::open(path.trim_start_matches('/'))?)
+}
+
+fn contents_hash<R: Read>(mut reader: R) -> Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    copy(&mut reader, &mut hasher)?;
+
+    Ok(hasher.finalize().to_vec())
+}
+
 pub fn reposition_to_db() -> Result<Option<String>> {
     let mut dir = current_dir()?;
     let dir_initial = dir.clone();
@@ -52,3 +76,11 @@ pub fn reposition_to_db() -> Result<Option<String>> {
         };
     }
 }
+
+#[test]
+fn test_contents_hash() {
+    let hex = |b: Vec<u8>| b.iter().map(|x| format!("{:02x}", x)).collect::<String>();
+
+    assert_eq!(hex(contents_hash(&b""[..]).unwrap()), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(hex(contents_hash(&b"abc"[..]).unwrap()), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+}
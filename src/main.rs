@@ -17,5 +17,5 @@ mod os;
 mod collections;
 
 fn main() {
-    println!("Hello, world!");
+    cli::args::parse_command_line_args();
 }